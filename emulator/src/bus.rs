@@ -9,12 +9,17 @@ use crate::{
         INTERRUPT_ENABLE_ADDRESS, INTERRUPT_REQUEST_ADDRESS, OAM_END_ADDRESS, OAM_START_ADDRESS,
         VRAM_END_ADDRESS, VRAM_START_ADDRESS,
     },
+    dma::{Hdma, OamDma},
     gpu::Gpu,
+    hooks::WriteHooks,
     interrupts::Interrupts,
     ram::Ram,
     register::Register8,
+    serial::{Serial, SerialLink},
+    speed::SpeedSwitch,
     spu::Spu,
     timer::Timer,
+    wram::Wram,
 };
 
 pub trait FetchWrite {
@@ -24,20 +29,24 @@ pub trait FetchWrite {
     fn write16(&mut self, address: u16, value: u16) -> std::io::Result<()>;
 }
 
-pub struct Bus<'a> {
+pub struct Bus {
+    cgb: bool,
     boot_rom: Option<BootRom>,
     boot_rom_enabled: Register8,
     cartridge: Cartridge,
-    wram: Ram,
+    wram: Wram,
     hram: Ram,
     pub interrupts: Interrupts,
-    pub gpu: Gpu<'a>,
-    serial_transfer: Register8,
-    serial_control: Register8,
-    spu: Spu,
+    pub gpu: Gpu,
+    oam_dma: OamDma,
+    hdma: Hdma,
+    speed_switch: SpeedSwitch,
+    serial: Serial,
+    pub spu: Spu,
     null: u8,
     timer: Timer,
     pub buttons: Buttons,
+    write_hooks: WriteHooks,
 }
 
 const BOOT_ROM_START_ADDRESS: u16 = 0x0;
@@ -61,60 +70,212 @@ const TIMER_END_ADDRESS: u16 = 0xFF07;
 const GPU_REGISTER_START_ADDRESS: u16 = 0xFF40;
 const GPU_REGISTER_END_ADDRESS: u16 = 0xFF4B;
 
-const SPU_REGISTER_START_ADDRESS: u16 = 0xFF24;
-const SPU_REGISTER_END_ADDRESS: u16 = 0xFF26;
+const SPU_REGISTER_START_ADDRESS: u16 = 0xFF10;
+const SPU_REGISTER_END_ADDRESS: u16 = 0xFF3F;
 
-const SERIAL_TRANSFER_REGISTER_ADDRESS: u16 = 0xFF01;
-const SERIAL_CONTROL_REGISTER_ADDRESS: u16 = 0xFF02;
+const SERIAL_START_ADDRESS: u16 = 0xFF01;
+const SERIAL_END_ADDRESS: u16 = 0xFF02;
 
 const HRAM_START_ADDRESS: u16 = 0xFF80;
 const HRAM_END_ADDRESS: u16 = 0xFFFE;
 
 const BUTTONS_REGISTER_ADDRESS: u16 = 0xFF00;
 
+const OAM_DMA_REGISTER_ADDRESS: u16 = 0xFF46;
+
+/// Everything from here up is reachable by the CPU during OAM DMA. Besides
+/// HRAM that includes the IO registers, which are not on the bus the
+/// transfer uses: writing the DMA register again restarts the transfer.
+const OAM_DMA_ACCESSIBLE_START_ADDRESS: u16 = 0xFF00;
+
 const BOOTROM_DISABLE_REGISTER_ADDRESS: u16 = 0xFF50;
 
-impl<'a> Bus<'a> {
-    pub fn new(cartridge: Cartridge, gpu: Gpu<'a>, bootrom_path: Option<String>) -> Self {
-        let wram = Ram::new(0x2000, WRAM_START_ADDRESS);
+const KEY1_REGISTER_ADDRESS: u16 = 0xFF4D;
+const VBK_REGISTER_ADDRESS: u16 = 0xFF4F;
+
+const HDMA_START_ADDRESS: u16 = 0xFF51;
+const HDMA_END_ADDRESS: u16 = 0xFF55;
+
+const CGB_PALETTE_START_ADDRESS: u16 = 0xFF68;
+const CGB_PALETTE_END_ADDRESS: u16 = 0xFF6B;
+
+const SVBK_REGISTER_ADDRESS: u16 = 0xFF70;
+
+const HDMA_BLOCK_LEN: u16 = 0x10;
+
+impl Bus {
+    pub fn new(cartridge: Cartridge, gpu: Gpu, bootrom_path: Option<String>) -> Self {
+        let cgb = cartridge.supports_cgb();
         let hram = Ram::new(0x7F, HRAM_START_ADDRESS);
-        let serial_transfer = 0u8;
-        let serial_control = 0u8;
-        let spu = Spu::new();
-        let boot_rom = match bootrom_path {
-            Some(path) => Some(BootRom::new(path)),
-            None => None,
-        };
+        let boot_rom = bootrom_path.map(BootRom::new);
 
-        Bus {
+        let mut bus = Bus {
+            cgb,
             boot_rom_enabled: if boot_rom.is_some() { 1 } else { 0 },
             boot_rom,
             cartridge,
-            wram,
+            wram: Wram::new(),
             hram,
             interrupts: Interrupts::new(),
             gpu,
-            serial_control,
-            serial_transfer,
-            spu,
+            oam_dma: OamDma::new(),
+            hdma: Hdma::new(),
+            speed_switch: SpeedSwitch::new(),
+            serial: Serial::new(),
+            spu: Spu::new(),
             null: 0,
             timer: Timer::new(),
             buttons: Buttons::new(),
-        }
+            write_hooks: WriteHooks::default(),
+        };
+        bus.set_cgb_mode(cgb);
+
+        bus
+    }
+
+    fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.gpu.set_cgb_mode(cgb);
+        self.wram.set_cgb_mode(cgb);
+        self.hdma.set_cgb_mode(cgb);
+        self.speed_switch.set_cgb_mode(cgb);
+        self.serial.set_cgb_mode(cgb);
+        self.spu.set_cgb_mode(cgb);
+    }
+
+    /// Whether the hardware runs in CGB mode. Only CGB games do, and not
+    /// when they run on a Super Game Boy.
+    pub fn is_cgb_mode(&self) -> bool {
+        self.cgb
     }
 
     pub fn next(&mut self, clock_cycles: u8) {
-        self.gpu.next(clock_cycles, &mut self.interrupts);
+        for _ in 0..clock_cycles / 4 {
+            self.oam_dma_next();
+        }
+
+        // The PPU keeps its pace when the CPU runs at double speed
+        let gpu_cycles = self.base_clock_cycles(clock_cycles);
+        self.gpu.next(gpu_cycles, &mut self.interrupts);
+        self.hdma_next();
+
+        self.buttons.next(&mut self.interrupts);
+        if let Some(command) = self.buttons.take_sgb_command() {
+            self.gpu.handle_sgb_command(&command);
+        }
+
         self.timer.next(clock_cycles, &mut self.interrupts);
+        self.serial.next(clock_cycles, &mut self.interrupts);
+        self.spu.next(
+            gpu_cycles,
+            self.timer.div(),
+            self.speed_switch.is_double_speed(),
+        );
+    }
+
+    /// Converts CPU clock cycles to cycles of the base clock the PPU and
+    /// the frame timing run on. In double speed they are half as many.
+    pub fn base_clock_cycles(&self, clock_cycles: u8) -> u8 {
+        if self.speed_switch.is_double_speed() {
+            clock_cycles / 2
+        } else {
+            clock_cycles
+        }
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    /// Advances what keeps running while STOP has the system clock off: the
+    /// joypad lines, and the serial port so a link cable stays in sync.
+    pub fn next_stopped(&mut self, clock_cycles: u8) {
+        self.buttons.next(&mut self.interrupts);
+        self.serial.next(clock_cycles, &mut self.interrupts);
+    }
+
+    /// Plugs the other end of the link cable in, or unplugs it with `None`.
+    pub fn set_serial_link(&mut self, link: Option<Box<dyn SerialLink>>) {
+        self.serial.set_link(link);
+    }
+
+    /// Unplugs the link cable, returns what was plugged in.
+    pub fn take_serial_link(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.serial.take_link()
+    }
+
+    /// Callbacks fired after CPU writes to chosen address ranges, e.g. the
+    /// LCD registers at `0xFF40..=0xFF4B`.
+    pub fn write_hooks_mut(&mut self) -> &mut WriteHooks {
+        &mut self.write_hooks
+    }
+
+    /// Turns on Super Game Boy functions if the cartridge supports them,
+    /// returns whether it does. The SGB is a DMG, so CGB games fall back
+    /// to DMG mode.
+    pub fn enable_sgb(&mut self) -> bool {
+        if !self.cartridge.supports_sgb() {
+            return false;
+        }
+
+        self.set_cgb_mode(false);
+        self.buttons.enable_sgb();
+        self.gpu.enable_sgb();
+        true
+    }
+
+    /// Performs a pending CGB speed switch, returns whether one happened.
+    pub fn switch_speed(&mut self) -> bool {
+        self.speed_switch.switch()
+    }
+
+    fn hdma_next(&mut self) {
+        let mut hblank_started = self.gpu.take_hblank_started();
+
+        while let Some((source, destination)) = self.hdma.next_block(hblank_started) {
+            hblank_started = false;
+
+            for offset in 0..HDMA_BLOCK_LEN {
+                let value = self.fetch8(source.wrapping_add(offset)).unwrap();
+                self.gpu.write_vram(destination + offset, value);
+            }
+        }
+    }
+
+    /// Copies the next OAM DMA byte. The transfer has its own path to the
+    /// memory, the PPU doesn't block it the way it blocks the CPU.
+    fn oam_dma_next(&mut self) {
+        if let Some((source, offset)) = self.oam_dma.next() {
+            let value = match source {
+                VRAM_START_ADDRESS..=VRAM_END_ADDRESS => self.gpu.read_dma_vram(source),
+                _ => self
+                    .get_address_target(source)
+                    .unwrap()
+                    .fetch8(source)
+                    .unwrap(),
+            };
+            self.gpu.write_oam(offset, value);
+        }
     }
 
+    /// While OAM DMA is running the CPU can only reach HRAM and the IO
+    /// registers, everything else reads as `0xFF` and ignores writes.
+    fn oam_dma_conflict(&self, address: u16) -> bool {
+        self.oam_dma.is_active() && address < OAM_DMA_ACCESSIBLE_START_ADDRESS
+    }
+
+    #[allow(clippy::match_overlapping_arm)]
     fn get_address_target(&mut self, address: u16) -> io::Result<&mut dyn FetchWrite> {
         match address {
             BOOT_ROM_START_ADDRESS..=BOOT_ROM_END_ADDRESS => {
-                if self.get_boot_rom_enabled() && self.boot_rom.is_some() {
-                    Ok(self.boot_rom.as_mut().unwrap())
-                } else {
-                    Ok(&mut self.cartridge)
+                let boot_rom_enabled = self.get_boot_rom_enabled();
+                match self.boot_rom.as_mut() {
+                    Some(boot_rom) if boot_rom_enabled => Ok(boot_rom),
+                    _ => Ok(&mut self.cartridge),
                 }
             }
             ROM_START_ADDRESS..=ROM_END_ADDRESS => Ok(&mut self.cartridge),
@@ -126,11 +287,16 @@ impl<'a> Bus<'a> {
             HRAM_START_ADDRESS..=HRAM_END_ADDRESS => Ok(&mut self.hram),
             INTERRUPT_REQUEST_ADDRESS => Ok(&mut self.interrupts),
             INTERRUPT_ENABLE_ADDRESS => Ok(&mut self.interrupts),
-            SERIAL_TRANSFER_REGISTER_ADDRESS => Ok(&mut self.serial_transfer),
-            SERIAL_CONTROL_REGISTER_ADDRESS => Ok(&mut self.serial_control),
+            SERIAL_START_ADDRESS..=SERIAL_END_ADDRESS => Ok(&mut self.serial),
             BUTTONS_REGISTER_ADDRESS => Ok(&mut self.buttons),
             BOOTROM_DISABLE_REGISTER_ADDRESS => Ok(&mut self.boot_rom_enabled),
+            OAM_DMA_REGISTER_ADDRESS => Ok(&mut self.oam_dma),
             GPU_REGISTER_START_ADDRESS..=GPU_REGISTER_END_ADDRESS => Ok(&mut self.gpu),
+            VBK_REGISTER_ADDRESS => Ok(&mut self.gpu),
+            CGB_PALETTE_START_ADDRESS..=CGB_PALETTE_END_ADDRESS => Ok(&mut self.gpu),
+            KEY1_REGISTER_ADDRESS => Ok(&mut self.speed_switch),
+            HDMA_START_ADDRESS..=HDMA_END_ADDRESS => Ok(&mut self.hdma),
+            SVBK_REGISTER_ADDRESS => Ok(&mut self.wram),
             SPU_REGISTER_START_ADDRESS..=SPU_REGISTER_END_ADDRESS => Ok(&mut self.spu),
             TIMER_START_ADDRESS..=TIMER_END_ADDRESS => Ok(&mut self.timer),
             _ => Ok(&mut self.null),
@@ -142,23 +308,43 @@ impl<'a> Bus<'a> {
     }
 }
 
-impl<'a> FetchWrite for Bus<'a> {
+impl FetchWrite for Bus {
     fn fetch8(&mut self, address: u16) -> io::Result<u8> {
+        if self.oam_dma_conflict(address) {
+            return Ok(0xFF);
+        }
+
         let target = self.get_address_target(address)?;
 
         target.fetch8(address)
     }
 
     fn fetch16(&mut self, address: u16) -> io::Result<u16> {
+        if self.oam_dma.is_active() {
+            let lo = self.fetch8(address)? as u16;
+            let hi = self.fetch8(address.wrapping_add(1))? as u16;
+
+            return Ok((hi << 8) | lo);
+        }
+
         let target = self.get_address_target(address)?;
 
         target.fetch16(address)
     }
 
     fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        if self.oam_dma_conflict(address) {
+            return Ok(());
+        }
+
         let target = self.get_address_target(address)?;
         target.write8(address, value)?;
 
+        if !self.write_hooks.is_empty() {
+            let context = self.gpu.hook_context();
+            self.write_hooks.call(address, value, &context);
+        }
+
         Ok(())
     }
 
@@ -166,3 +352,134 @@ impl<'a> FetchWrite for Bus<'a> {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OAM_LEN: u16 = 0xA0;
+    const LCDC_ADDRESS: u16 = 0xFF40;
+
+    fn bus() -> Bus {
+        let rom = (0..0x8000).map(|address| address as u8 | 1).collect();
+        let mut bus = Bus::new(Cartridge::from_rom(rom), Gpu::new(), None);
+
+        for offset in 0..OAM_LEN {
+            bus.write8(0xC000 + offset, offset as u8).unwrap();
+            bus.write8(0xC100 + offset, !offset as u8).unwrap();
+        }
+
+        bus
+    }
+
+    #[test]
+    fn oam_dma_starts_after_one_m_cycle() {
+        let mut bus = bus();
+        bus.write8(OAM_DMA_REGISTER_ADDRESS, 0xC0).unwrap();
+
+        bus.next(4);
+        assert_eq!(bus.fetch8(0x0100).unwrap(), 0x01);
+
+        bus.next(4);
+        assert_eq!(bus.fetch8(0x0100).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn oam_dma_blocks_all_but_hram_and_io() {
+        let mut bus = bus();
+        bus.write8(OAM_DMA_REGISTER_ADDRESS, 0xC0).unwrap();
+        bus.next(8);
+
+        assert_eq!(bus.fetch8(0x0100).unwrap(), 0xFF);
+        assert_eq!(bus.fetch8(OAM_START_ADDRESS).unwrap(), 0xFF);
+        assert_eq!(bus.fetch8(0xC000).unwrap(), 0xFF);
+
+        bus.write8(0xFF80, 0x42).unwrap();
+        assert_eq!(bus.fetch8(0xFF80).unwrap(), 0x42);
+        assert_eq!(bus.fetch8(OAM_DMA_REGISTER_ADDRESS).unwrap(), 0xC0);
+
+        // 160 M-cycles copy everything
+        for _ in 0..OAM_LEN {
+            bus.next(4);
+        }
+        assert_eq!(bus.fetch8(0x0100).unwrap(), 0x01);
+        for offset in 0..OAM_LEN {
+            assert_eq!(
+                bus.fetch8(OAM_START_ADDRESS + offset).unwrap(),
+                offset as u8
+            );
+        }
+    }
+
+    #[test]
+    fn oam_dma_reads_vram_while_ppu_draws() {
+        let mut bus = bus();
+        for offset in 0..OAM_LEN {
+            bus.write8(VRAM_START_ADDRESS + offset, 0x80 | offset as u8)
+                .unwrap();
+        }
+
+        // The transfer runs through a whole line with the LCD on
+        bus.write8(LCDC_ADDRESS, 0x80).unwrap();
+        bus.write8(OAM_DMA_REGISTER_ADDRESS, 0x80).unwrap();
+        for _ in 0..=OAM_LEN {
+            bus.next(4);
+        }
+
+        bus.write8(LCDC_ADDRESS, 0).unwrap();
+        bus.next(4);
+        for offset in 0..OAM_LEN {
+            assert_eq!(
+                bus.fetch8(OAM_START_ADDRESS + offset).unwrap(),
+                0x80 | offset as u8
+            );
+        }
+    }
+
+    #[test]
+    fn general_purpose_hdma_copies_to_vram() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80; // CGB flag
+        let mut bus = Bus::new(Cartridge::from_rom(rom), Gpu::new(), None);
+        for offset in 0..0x20 {
+            bus.write8(0xC000 + offset, 0x40 | offset as u8).unwrap();
+        }
+
+        bus.write8(HDMA_START_ADDRESS, 0xC0).unwrap();
+        bus.write8(HDMA_START_ADDRESS + 1, 0x00).unwrap();
+        bus.write8(HDMA_START_ADDRESS + 2, 0x01).unwrap();
+        bus.write8(HDMA_START_ADDRESS + 3, 0x00).unwrap();
+        bus.write8(HDMA_END_ADDRESS, 0x01).unwrap();
+        bus.next(4);
+
+        for offset in 0..0x20 {
+            assert_eq!(bus.fetch8(0x8100 + offset).unwrap(), 0x40 | offset as u8);
+        }
+        assert_eq!(bus.fetch8(HDMA_END_ADDRESS).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn oam_dma_restarts_from_new_source() {
+        let mut bus = bus();
+        bus.write8(OAM_DMA_REGISTER_ADDRESS, 0xC0).unwrap();
+        for _ in 0..OAM_LEN / 2 {
+            bus.next(4);
+        }
+
+        bus.write8(OAM_DMA_REGISTER_ADDRESS, 0xC1).unwrap();
+        for _ in 0..OAM_LEN {
+            bus.next(4);
+            // The old transfer keeps the bus until the new one starts
+            assert_eq!(bus.fetch8(0x0100).unwrap(), 0xFF);
+        }
+
+        bus.next(4);
+        assert_eq!(bus.fetch8(0x0100).unwrap(), 0x01);
+        for offset in 0..OAM_LEN {
+            assert_eq!(
+                bus.fetch8(OAM_START_ADDRESS + offset).unwrap(),
+                !offset as u8
+            );
+        }
+    }
+}
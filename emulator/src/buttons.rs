@@ -1,4 +1,8 @@
-use crate::bus::FetchWrite;
+use crate::{
+    bus::FetchWrite,
+    interrupts::Interrupts,
+    sgb::{self, PacketReader, MLT_REQ},
+};
 
 const P10_RIGHT_OR_A_BITMASK: u8 = 1;
 const P11_LEFT_OR_B_BITMASK: u8 = 1 << 1;
@@ -7,6 +11,12 @@ const P13_DOWN_OR_START_BITMASK: u8 = 1 << 3;
 const P14_SELECT_DIRECTION_BITMASK: u8 = 1 << 4;
 const P15_SELECT_ACTION_BITMASK: u8 = 1 << 5;
 
+const INPUT_LINES_BITMASK: u8 = 0x0F;
+const SELECT_LINES_BITMASK: u8 = P14_SELECT_DIRECTION_BITMASK | P15_SELECT_ACTION_BITMASK;
+
+/// P1 joypad register. The buttons form a matrix: P14 and P15 select the
+/// direction and action rows, and a pressed button of a selected row pulls
+/// its line P10-P13 low. A line going low requests the joypad interrupt.
 pub struct Buttons {
     left: bool,
     right: bool,
@@ -18,6 +28,18 @@ pub struct Buttons {
     select: bool,
     directions: bool,
     actions: bool,
+    /// P10-P13 as last seen, to find falling edges
+    input_lines: u8,
+    sgb_packets: Option<PacketReader>,
+    /// Number of SGB joypads requested with MLT_REQ
+    players: u8,
+    current_player: u8,
+}
+
+impl Default for Buttons {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Buttons {
@@ -33,7 +55,79 @@ impl Buttons {
             select: false,
             directions: false,
             actions: false,
+            input_lines: INPUT_LINES_BITMASK,
+            sgb_packets: None,
+            players: 1,
+            current_player: 0,
+        }
+    }
+
+    /// Starts listening for SGB command packets on P14/P15.
+    pub fn enable_sgb(&mut self) {
+        self.sgb_packets = Some(PacketReader::new());
+    }
+
+    /// Returns the last completed SGB command. Multiplayer requests are
+    /// handled here, as they change what P1 reads.
+    pub fn take_sgb_command(&mut self) -> Option<Vec<u8>> {
+        let command = self.sgb_packets.as_mut()?.take_command()?;
+
+        if sgb::command_code(&command) == MLT_REQ {
+            self.players = match command[1] & 0b11 {
+                1 => 2,
+                3 => 4,
+                _ => 1,
+            };
+            self.current_player = 0;
+        }
+
+        Some(command)
+    }
+
+    /// Requests the joypad interrupt when an input line went from high to
+    /// low since the last call.
+    pub(crate) fn next(&mut self, interrupts: &mut Interrupts) {
+        let input_lines = self.input_lines();
+        if self.input_lines & !input_lines != 0 {
+            interrupts.set_joypad_request(true);
+        }
+        self.input_lines = input_lines;
+    }
+
+    /// Whether a pressed button pulls a selected line low, which ends STOP.
+    pub fn any_line_low(&self) -> bool {
+        self.input_lines() != INPUT_LINES_BITMASK
+    }
+
+    /// P10-P13, low for pressed buttons of the selected rows. With both
+    /// rows selected a line is low if either of its buttons is pressed.
+    fn input_lines(&self) -> u8 {
+        // The joypads of other SGB players are not connected
+        if self.current_player != 0 {
+            return INPUT_LINES_BITMASK;
+        }
+
+        let mut pressed = 0;
+        let mut press = |value: bool, bitmask: u8| {
+            if value {
+                pressed |= bitmask;
+            }
+        };
+
+        if self.directions {
+            press(self.right, P10_RIGHT_OR_A_BITMASK);
+            press(self.left, P11_LEFT_OR_B_BITMASK);
+            press(self.up, P12_UP_OR_SELECT_BITMASK);
+            press(self.down, P13_DOWN_OR_START_BITMASK);
+        }
+        if self.actions {
+            press(self.a, P10_RIGHT_OR_A_BITMASK);
+            press(self.b, P11_LEFT_OR_B_BITMASK);
+            press(self.select, P12_UP_OR_SELECT_BITMASK);
+            press(self.start, P13_DOWN_OR_START_BITMASK);
         }
+
+        INPUT_LINES_BITMASK & !pressed
     }
 
     pub fn set_left(&mut self, value: bool) {
@@ -64,36 +158,22 @@ impl Buttons {
 
 impl FetchWrite for Buttons {
     fn fetch8(&mut self, _: u16) -> Result<u8, std::io::Error> {
-        let mut button_register: u8 = 0xC0;
-        if self.directions {
-            if !self.left {
-                button_register |= P11_LEFT_OR_B_BITMASK;
-            }
-            if !self.right {
-                button_register |= P10_RIGHT_OR_A_BITMASK;
-            }
-            if !self.up {
-                button_register |= P12_UP_OR_SELECT_BITMASK;
-            }
-            if !self.down {
-                button_register |= P13_DOWN_OR_START_BITMASK;
-            }
+        let mut select_lines = 0;
+        if !self.directions {
+            select_lines |= P14_SELECT_DIRECTION_BITMASK;
         }
-        if self.actions {
-            if !self.a {
-                button_register |= P10_RIGHT_OR_A_BITMASK;
-            }
-            if !self.b {
-                button_register |= P11_LEFT_OR_B_BITMASK;
-            }
-            if !self.start {
-                button_register |= P13_DOWN_OR_START_BITMASK;
-            }
-            if !self.select {
-                button_register |= P12_UP_OR_SELECT_BITMASK;
-            }
+        if !self.actions {
+            select_lines |= P15_SELECT_ACTION_BITMASK;
+        }
+        let button_register = 0xC0 | select_lines;
+
+        // With multiplayer enabled, deselecting both lines reads the ID of
+        // the current joypad
+        if self.players > 1 && select_lines == SELECT_LINES_BITMASK {
+            return Ok(button_register | (INPUT_LINES_BITMASK - self.current_player));
         }
-        Ok(button_register)
+
+        Ok(button_register | self.input_lines())
     }
 
     fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
@@ -101,8 +181,20 @@ impl FetchWrite for Buttons {
     }
 
     fn write8(&mut self, _: u16, value: u8) -> std::io::Result<()> {
+        let actions = value & P15_SELECT_ACTION_BITMASK == 0;
+
+        // The SGB switches to the next joypad when P15 goes high
+        if self.players > 1 && self.actions && !actions {
+            self.current_player = (self.current_player + 1) % self.players;
+        }
+
         self.directions = value & P14_SELECT_DIRECTION_BITMASK == 0;
-        self.actions = value & P15_SELECT_ACTION_BITMASK == 0;
+        self.actions = actions;
+
+        if let Some(packets) = self.sgb_packets.as_mut() {
+            packets.write(value);
+        }
+
         Ok(())
     }
 
@@ -7,9 +7,28 @@ use crate::{bus::FetchWrite, ram::Ram};
 
 const ERAM_ADDRESS_OFFSET: u16 = 0xA000;
 
+const CGB_FLAG_ADDRESS: usize = 0x143;
+const CGB_SUPPORT_BITMASK: u8 = 1 << 7;
+const SGB_FLAG_ADDRESS: usize = 0x146;
+const SGB_SUPPORT: u8 = 0x03;
+const OLD_LICENSEE_CODE_ADDRESS: usize = 0x14B;
+/// Old licensee code that defers to the new one, required for SGB functions
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+const NINTENDO_LICENSEE_CODE: u8 = 0x01;
+const NEW_LICENSEE_CODE_ADDRESS: usize = 0x144;
+const NINTENDO_NEW_LICENSEE_CODE: &[u8] = b"01";
+const TITLE_ADDRESS: usize = 0x134;
+const TITLE_LEN: usize = 16;
+
+const ROM_BANK_LEN: usize = 0x4000;
+const SWITCHABLE_BANK_START_ADDRESS: u16 = 0x4000;
+const ROM_BANK_SELECT_START_ADDRESS: u16 = 0x2000;
+const ROM_BANK_SELECT_END_ADDRESS: u16 = 0x3FFF;
+
 pub struct Cartridge {
     rom: Ram,
     eram: Ram,
+    rom_bank: usize,
 }
 
 impl Cartridge {
@@ -18,19 +37,84 @@ impl Cartridge {
         let mut file = File::open(path).unwrap();
         file.read_exact(&mut rom.buffer).unwrap();
 
+        Cartridge::from_rom(rom.buffer)
+    }
+
+    /// Maps a ROM image of whole 16 KiB banks. Images larger than 32 KiB
+    /// switch the bank at `4000-7FFF` on writes to `2000-3FFF`.
+    pub fn from_rom(rom: Vec<u8>) -> Self {
+        let mut rom_ram = Ram::new(0, 0);
+        rom_ram.buffer = rom;
+
         Cartridge {
-            rom,
+            rom: rom_ram,
             eram: Ram::new(0x2000, ERAM_ADDRESS_OFFSET),
+            rom_bank: 1,
         }
     }
 
-    // fn parse_header() {}
+    fn is_banked(&self) -> bool {
+        self.rom.buffer.len() > 2 * ROM_BANK_LEN
+    }
+
+    fn select_rom_bank(&mut self, bank: u8) {
+        let banks = self.rom.buffer.len() / ROM_BANK_LEN;
+        self.rom_bank = (bank.max(1) as usize) % banks;
+    }
+
+    fn fetch_rom(&self, address: u16) -> u8 {
+        if self.is_banked() && address >= SWITCHABLE_BANK_START_ADDRESS {
+            let offset = (address - SWITCHABLE_BANK_START_ADDRESS) as usize;
+            return self.rom.buffer[self.rom_bank * ROM_BANK_LEN + offset];
+        }
+
+        self.rom.buffer[address as usize]
+    }
+
+    /// Contents of the external RAM, which battery backed cartridges keep
+    /// as save data.
+    pub fn ram(&self) -> &[u8] {
+        &self.eram.buffer
+    }
+
+    /// Restores the external RAM from save data. Extra bytes are ignored.
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.eram.buffer.len());
+        self.eram.buffer[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Whether the header marks the game as CGB enhanced or CGB only.
+    pub fn supports_cgb(&self) -> bool {
+        self.rom.buffer[CGB_FLAG_ADDRESS] & CGB_SUPPORT_BITMASK != 0
+    }
+
+    /// Title from the header, padded with zeros.
+    pub fn title(&self) -> &[u8] {
+        &self.rom.buffer[TITLE_ADDRESS..TITLE_ADDRESS + TITLE_LEN]
+    }
+
+    pub fn is_licensed_by_nintendo(&self) -> bool {
+        match self.rom.buffer[OLD_LICENSEE_CODE_ADDRESS] {
+            NINTENDO_LICENSEE_CODE => true,
+            USE_NEW_LICENSEE_CODE => {
+                &self.rom.buffer[NEW_LICENSEE_CODE_ADDRESS..NEW_LICENSEE_CODE_ADDRESS + 2]
+                    == NINTENDO_NEW_LICENSEE_CODE
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the header enables Super Game Boy functions.
+    pub fn supports_sgb(&self) -> bool {
+        self.rom.buffer[SGB_FLAG_ADDRESS] == SGB_SUPPORT
+            && self.rom.buffer[OLD_LICENSEE_CODE_ADDRESS] == USE_NEW_LICENSEE_CODE
+    }
 }
 
 impl FetchWrite for Cartridge {
     fn fetch8(&mut self, address: u16) -> io::Result<u8> {
         if address < ERAM_ADDRESS_OFFSET {
-            self.rom.fetch8(address)
+            Ok(self.fetch_rom(address))
         } else {
             self.eram.fetch8(address)
         }
@@ -38,7 +122,9 @@ impl FetchWrite for Cartridge {
 
     fn fetch16(&mut self, address: u16) -> io::Result<u16> {
         if address < ERAM_ADDRESS_OFFSET {
-            self.rom.fetch16(address)
+            let lo = self.fetch_rom(address) as u16;
+            let hi = self.fetch_rom(address + 1) as u16;
+            Ok((hi << 8) | lo)
         } else {
             self.eram.fetch16(address)
         }
@@ -46,6 +132,11 @@ impl FetchWrite for Cartridge {
 
     fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
         if address < ERAM_ADDRESS_OFFSET {
+            if self.is_banked()
+                && (ROM_BANK_SELECT_START_ADDRESS..=ROM_BANK_SELECT_END_ADDRESS).contains(&address)
+            {
+                self.select_rom_bank(value);
+            }
             return Ok(());
             //panic!("Cannot write to cartridge ROM!")
         }
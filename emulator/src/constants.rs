@@ -22,3 +22,9 @@ pub const VRAM_END_ADDRESS: u16 = 0x9FFF;
 
 pub const OAM_START_ADDRESS: u16 = 0xFE00;
 pub const OAM_END_ADDRESS: u16 = 0xFE9F;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// Clock cycles of one frame, including VBlank
+pub const FRAME_CYCLES: i64 = 70224;
@@ -43,6 +43,7 @@ pub enum Instruction {
     RETI,
     PREFIX,
     NOP,
+    STOP,
     UNDEFINED,
 }
 
@@ -227,7 +228,7 @@ pub const OPCODES: [(Instruction, u8); 256] = [
     ),
     (Instruction::UNDEFINED, 4),
     // 1X
-    (Instruction::UNDEFINED, 4),
+    (Instruction::STOP, 4),
     (
         Instruction::LD(
             LoadType::Word(LoadWordTarget::DE, LoadWordSource::N16),
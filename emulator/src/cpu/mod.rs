@@ -29,6 +29,12 @@ pub struct Cpu {
     l: u8,
     program_counter: u16,
     stack_pointer: u16,
+    /// Halted by STOP until a button is pressed
+    stopped: bool,
+    /// Clock cycles of the current instruction the bus has run for
+    ticked: u8,
+    /// The current instruction accessed memory already
+    accessed: bool,
     disassembler: Disassembler,
 }
 
@@ -38,10 +44,11 @@ const HALFCARRY_FLAG_MASK: u8 = 1 << 5;
 const CARRY_FLAG_MASK: u8 = 1 << 4;
 
 impl Cpu {
-    pub fn new(skip_boot: bool, disassemble: bool) -> Self {
+    pub fn new(skip_boot: bool, disassemble: bool, cgb: bool) -> Self {
         if skip_boot {
             Cpu {
-                a: 0x1,
+                // Games tell the hardware models apart by the value of A
+                a: if cgb { 0x11 } else { 0x1 },
                 b: 0x0,
                 c: 0x13,
                 d: 0x0,
@@ -51,6 +58,9 @@ impl Cpu {
                 l: 0x4D,
                 program_counter: 0x100,
                 stack_pointer: 0xFFFE,
+                stopped: false,
+                ticked: 0,
+                accessed: false,
                 disassembler: Disassembler::new(disassemble),
             }
         } else {
@@ -65,29 +75,65 @@ impl Cpu {
                 l: 0,
                 program_counter: 0,
                 stack_pointer: 0,
+                stopped: false,
+                ticked: 0,
+                accessed: false,
                 disassembler: Disassembler::new(disassemble),
             }
         }
     }
     pub fn next(&mut self, bus: &mut Bus) -> std::io::Result<u8> {
+        if self.stopped {
+            // The system clock is off until a selected button line goes low
+            bus.next_stopped(4);
+            if bus.buttons.any_line_low() {
+                self.stopped = false;
+            }
+            return Ok(4);
+        }
+
+        self.ticked = 0;
+        self.accessed = false;
+
         if bus.interrupts.interrupt_pending() {
             self.handle_interrupt(bus);
         }
         let opcode = self.next_byte(bus)?;
         let (instruction, cycles) = self.decode_instruction(bus, opcode)?;
 
-        match self.run_instruction(bus, instruction) {
-            Err(_) => {
-                self.unwind_stack(bus);
-                panic!("Unsupported opcode {:#X}", opcode)
-            }
-            Ok(()) => {}
-        };
-        bus.next(cycles);
+        if self.run_instruction(bus, instruction).is_err() {
+            self.unwind_stack(bus);
+            panic!("Unsupported opcode {:#X}", opcode)
+        }
+
+        // The last access takes an M-cycle too
+        let cycles = cycles.max(self.ticked + 4);
+        bus.next(cycles - self.ticked);
 
         Ok(cycles)
     }
 
+    /// Runs the bus up to the M-cycle of the next memory access, so the
+    /// access sees the timer and other hardware as they are at that point
+    /// of the instruction. The first access is in the first M-cycle.
+    fn sync_bus(&mut self, bus: &mut Bus) {
+        if self.accessed {
+            bus.next(4);
+            self.ticked += 4;
+        }
+        self.accessed = true;
+    }
+
+    fn read8(&mut self, bus: &mut Bus, address: u16) -> io::Result<u8> {
+        self.sync_bus(bus);
+        bus.fetch8(address)
+    }
+
+    fn write8(&mut self, bus: &mut Bus, address: u16, value: u8) -> io::Result<()> {
+        self.sync_bus(bus);
+        bus.write8(address, value)
+    }
+
     fn decode_instruction(
         &mut self,
         bus: &mut Bus,
@@ -104,6 +150,7 @@ impl Cpu {
     fn run_instruction(&mut self, bus: &mut Bus, instruction: Instruction) -> io::Result<()> {
         match instruction {
             Instruction::NOP => {}
+            Instruction::STOP => self.stop(bus),
             Instruction::LD(load_type, load_operation) => self.ld(bus, load_type, load_operation),
             Instruction::JP(condition, target) => self.jp(bus, condition, target),
             Instruction::JR(condition) => self.jr(bus, condition),
@@ -148,33 +195,33 @@ impl Cpu {
     }
 
     fn next_byte(&mut self, bus: &mut Bus) -> std::io::Result<u8> {
-        let val = bus.fetch8(self.program_counter);
+        let val = self.read8(bus, self.program_counter);
         self.program_counter += 0x1;
 
         val
     }
 
     fn next_word(&mut self, bus: &mut Bus) -> std::io::Result<u16> {
-        let val = bus.fetch16(self.program_counter);
-        self.program_counter += 0x2;
+        let lo = self.next_byte(bus)? as u16;
+        let hi = self.next_byte(bus)? as u16;
 
-        val
+        Ok((hi << 8) | lo)
     }
 
     fn af(&self) -> u16 {
-        return ((self.a as u16) << 8) | (self.f as u16);
+        ((self.a as u16) << 8) | (self.f as u16)
     }
 
     fn hl(&self) -> u16 {
-        return ((self.h as u16) << 8) | (self.l as u16);
+        ((self.h as u16) << 8) | (self.l as u16)
     }
 
     fn bc(&self) -> u16 {
-        return ((self.b as u16) << 8) | (self.c as u16);
+        ((self.b as u16) << 8) | (self.c as u16)
     }
 
     fn de(&self) -> u16 {
-        return ((self.d as u16) << 8) | (self.e as u16);
+        ((self.d as u16) << 8) | (self.e as u16)
     }
 
     fn set_a(&mut self, value: u8) {
@@ -229,6 +276,26 @@ impl Cpu {
         self.set_l(l);
     }
 
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// Calls the subroutine at `address` with `a` in register A and the
+    /// stack at `stack_pointer`. It returns to `return_address`.
+    pub fn call_subroutine(
+        &mut self,
+        bus: &mut Bus,
+        address: u16,
+        a: u8,
+        stack_pointer: u16,
+        return_address: u16,
+    ) {
+        self.set_a(a);
+        self.set_stack_pointer(stack_pointer);
+        self.push16(bus, return_address);
+        self.set_program_counter(address);
+    }
+
     pub fn set_af(&mut self, value: u16) {
         self.a = (value >> 8) as u8;
         self.f = (value & 0x00F0) as u8;
@@ -292,7 +359,7 @@ impl Cpu {
 
     fn push8(&mut self, bus: &mut Bus, value: u8) {
         self.set_stack_pointer(self.stack_pointer - 1);
-        bus.write8(self.stack_pointer, value).unwrap();
+        self.write8(bus, self.stack_pointer, value).unwrap();
     }
 
     fn push16(&mut self, bus: &mut Bus, value: u16) {
@@ -301,7 +368,7 @@ impl Cpu {
     }
 
     fn pop8(&mut self, bus: &mut Bus) -> u8 {
-        let value = bus.fetch8(self.stack_pointer).unwrap();
+        let value = self.read8(bus, self.stack_pointer).unwrap();
         self.set_stack_pointer(self.stack_pointer + 1);
 
         value
@@ -335,14 +402,12 @@ impl Cpu {
         self.set_program_counter(address);
     }
 
-    fn dma_transfer(&mut self, bus: &mut Bus) {
-        let start_address = (bus.gpu.get_dma() as u16) << 8;
-        let end_address = start_address | 0x9F;
+    fn stop(&mut self, bus: &mut Bus) {
+        // STOP is followed by a padding byte
+        self.next_byte(bus).unwrap();
 
-        for address in start_address..=end_address {
-            let value = bus.fetch8(address).unwrap();
-            let destination_address = (address & 0x00FF) | 0xFE00;
-            bus.write8(destination_address, value).unwrap();
+        if !bus.switch_speed() {
+            self.stopped = true;
         }
     }
 
@@ -500,7 +565,7 @@ impl Cpu {
     fn swap(&mut self, bus: &mut Bus, target: ArithmeticByteTarget) {
         let value = self.read_arithmetic_byte_target(bus, target);
 
-        let result = (value >> 4) | (value << 4);
+        let result = value.rotate_left(4);
         self.write_arithmetic_byte_target(bus, target, result);
 
         self.set_zero(result == 0);
@@ -599,6 +664,7 @@ impl Cpu {
             }
             ArithmeticType::Word(target) => {
                 let value = self.read_arithmetic_word_target(target);
+                self.oam_corruption_check(bus, value);
                 self.write_arithmetic_word_target(target, value.wrapping_add(1));
             }
         }
@@ -619,11 +685,18 @@ impl Cpu {
             }
             ArithmeticType::Word(target) => {
                 let value = self.read_arithmetic_word_target(target);
+                self.oam_corruption_check(bus, value);
                 self.write_arithmetic_word_target(target, value.wrapping_sub(1));
             }
         }
     }
 
+    fn oam_corruption_check(&self, bus: &mut Bus, address: u16) {
+        if (0xFE00..=0xFEFF).contains(&address) {
+            bus.gpu.trigger_oam_corruption();
+        }
+    }
+
     fn or(&mut self, bus: &mut Bus, target: ArithmeticByteTarget) {
         let value = self.read_arithmetic_byte_target(bus, target);
 
@@ -669,23 +742,23 @@ impl Cpu {
                     LoadByteSource::E => self.e,
                     LoadByteSource::H => self.h,
                     LoadByteSource::L => self.l,
-                    LoadByteSource::MHL => bus.fetch8(self.hl()).unwrap(),
-                    LoadByteSource::MBC => bus.fetch8(self.bc()).unwrap(),
-                    LoadByteSource::MDE => bus.fetch8(self.de()).unwrap(),
+                    LoadByteSource::MHL => self.read8(bus, self.hl()).unwrap(),
+                    LoadByteSource::MBC => self.read8(bus, self.bc()).unwrap(),
+                    LoadByteSource::MDE => self.read8(bus, self.de()).unwrap(),
                     LoadByteSource::N8 => self.next_byte(bus).unwrap(),
                     LoadByteSource::DN8 => {
                         let mut address = self.next_byte(bus).unwrap() as u16;
                         address |= 0xFF00;
-                        bus.fetch8(address).unwrap()
+                        self.read8(bus, address).unwrap()
                     }
                     LoadByteSource::DC => {
                         let mut address = self.c as u16;
                         address |= 0xFF00;
-                        bus.fetch8(address).unwrap()
+                        self.read8(bus, address).unwrap()
                     }
                     LoadByteSource::MN16 => {
                         let address = self.next_word(bus).unwrap();
-                        bus.fetch8(address).unwrap()
+                        self.read8(bus, address).unwrap()
                     }
                 };
 
@@ -697,25 +770,21 @@ impl Cpu {
                     LoadByteTarget::E => self.set_e(source_value),
                     LoadByteTarget::H => self.set_h(source_value),
                     LoadByteTarget::L => self.set_l(source_value),
-                    LoadByteTarget::MHL => bus.write8(self.hl(), source_value).unwrap(),
-                    LoadByteTarget::MBC => bus.write8(self.bc(), source_value).unwrap(),
-                    LoadByteTarget::MDE => bus.write8(self.de(), source_value).unwrap(),
+                    LoadByteTarget::MHL => self.write8(bus, self.hl(), source_value).unwrap(),
+                    LoadByteTarget::MBC => self.write8(bus, self.bc(), source_value).unwrap(),
+                    LoadByteTarget::MDE => self.write8(bus, self.de(), source_value).unwrap(),
                     LoadByteTarget::MN16 => {
                         let address = self.next_word(bus).unwrap();
-                        bus.write8(address, source_value).unwrap();
+                        self.write8(bus, address, source_value).unwrap();
                     }
                     LoadByteTarget::DN8 => {
                         let n = self.next_byte(bus).unwrap();
                         let address = n as u16 | 0xFF00;
-                        bus.write8(address, source_value).unwrap();
-
-                        if address == 0xFF46 {
-                            self.dma_transfer(bus);
-                        }
+                        self.write8(bus, address, source_value).unwrap();
                     }
                     LoadByteTarget::DC => {
                         let address = self.c as u16 | 0xFF00;
-                        bus.write8(address, source_value).unwrap();
+                        self.write8(bus, address, source_value).unwrap();
                     }
                 };
             }
@@ -731,7 +800,9 @@ impl Cpu {
                     LoadWordTarget::DE => self.set_de(source_value),
                     LoadWordTarget::MN16 => {
                         let address = self.next_word(bus).unwrap();
-                        bus.write16(address, source_value).unwrap();
+                        self.write8(bus, address, source_value as u8).unwrap();
+                        self.write8(bus, address.wrapping_add(1), (source_value >> 8) as u8)
+                            .unwrap();
                     }
                 }
             }
@@ -783,7 +854,7 @@ impl Cpu {
     }
 
     fn read_arithmetic_byte_target(&mut self, bus: &mut Bus, target: ArithmeticByteTarget) -> u8 {
-        return match target {
+        match target {
             ArithmeticByteTarget::A => self.a,
             ArithmeticByteTarget::B => self.b,
             ArithmeticByteTarget::C => self.c,
@@ -791,9 +862,9 @@ impl Cpu {
             ArithmeticByteTarget::E => self.e,
             ArithmeticByteTarget::H => self.h,
             ArithmeticByteTarget::L => self.l,
-            ArithmeticByteTarget::MHL => bus.fetch8(self.hl()).unwrap(),
+            ArithmeticByteTarget::MHL => self.read8(bus, self.hl()).unwrap(),
             ArithmeticByteTarget::N8 => self.next_byte(bus).unwrap(),
-        };
+        }
     }
 
     fn write_arithmetic_byte_target(
@@ -810,18 +881,18 @@ impl Cpu {
             ArithmeticByteTarget::E => self.set_e(value),
             ArithmeticByteTarget::H => self.set_h(value),
             ArithmeticByteTarget::L => self.set_l(value),
-            ArithmeticByteTarget::MHL => bus.write8(self.hl(), value).unwrap(),
+            ArithmeticByteTarget::MHL => self.write8(bus, self.hl(), value).unwrap(),
             ArithmeticByteTarget::N8 => {}
         }
     }
 
     fn read_arithmetic_word_target(&mut self, target: ArithmeticWordTarget) -> u16 {
-        return match target {
+        match target {
             ArithmeticWordTarget::BC => self.bc(),
             ArithmeticWordTarget::HL => self.hl(),
             ArithmeticWordTarget::AF => self.af(),
             ArithmeticWordTarget::DE => self.de(),
-        };
+        }
     }
 
     fn write_arithmetic_word_target(&mut self, target: ArithmeticWordTarget, value: u16) {
@@ -851,3 +922,143 @@ impl Cpu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge::Cartridge, gpu::Gpu};
+
+    const INTERRUPT_REQUEST_ADDRESS: u16 = 0xFF0F;
+    const JOYPAD_BITMASK: u8 = 1 << 4;
+
+    const LCDC_ADDRESS: u16 = 0xFF40;
+    const KEY1_ADDRESS: u16 = 0xFF4D;
+    const OAM_START_ADDRESS: u16 = 0xFE00;
+    const OAM_LEN: usize = 0xA0;
+    const OAM_ROW_LEN: usize = 8;
+
+    /// Runs `program` with the LCD just turned on, returns OAM before and
+    /// after.
+    fn run_with_oam(program: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        let mut bus = Bus::new(Cartridge::from_rom(rom), Gpu::new(), None);
+        let mut cpu = Cpu::new(true, false, false);
+
+        let oam: Vec<u8> = (0..OAM_LEN).map(|offset| (offset * 7) as u8).collect();
+        for (offset, value) in oam.iter().enumerate() {
+            bus.write8(OAM_START_ADDRESS + offset as u16, *value)
+                .unwrap();
+        }
+
+        bus.write8(LCDC_ADDRESS, 0x80).unwrap();
+        while cpu.program_counter() < 0x100 + program.len() as u16 {
+            cpu.next(&mut bus).unwrap();
+        }
+        bus.write8(LCDC_ADDRESS, 0).unwrap();
+        bus.next(4);
+
+        let corrupted = (0..OAM_LEN)
+            .map(|offset| bus.fetch8(OAM_START_ADDRESS + offset as u16).unwrap())
+            .collect();
+
+        (oam, corrupted)
+    }
+
+    fn assert_row_corrupted(oam: &[u8], corrupted: &[u8]) {
+        let rows: Vec<usize> = (0..OAM_LEN / OAM_ROW_LEN)
+            .filter(|row| {
+                let range = row * OAM_ROW_LEN..(row + 1) * OAM_ROW_LEN;
+                oam[range.clone()] != corrupted[range]
+            })
+            .collect();
+        assert_eq!(rows.len(), 1, "corrupted rows {:?}", rows);
+
+        let current = rows[0] * OAM_ROW_LEN;
+        let previous = current - OAM_ROW_LEN;
+        let word = |offset: usize| u16::from_le_bytes([oam[offset], oam[offset + 1]]);
+        let (a, b, c) = (word(current), word(previous), word(previous + 4));
+
+        assert_eq!(
+            corrupted[current..current + 2],
+            (((a ^ c) & (b ^ c)) ^ c).to_le_bytes()
+        );
+        assert_eq!(
+            corrupted[current + 2..current + OAM_ROW_LEN],
+            oam[previous + 2..previous + OAM_ROW_LEN]
+        );
+    }
+
+    #[test]
+    fn inc_into_oam_corrupts_row_during_scan() {
+        // LD HL,FE00 / INC HL
+        let (oam, corrupted) = run_with_oam(&[0x21, 0x00, 0xFE, 0x23]);
+
+        assert_row_corrupted(&oam, &corrupted);
+    }
+
+    #[test]
+    fn dec_into_oam_corrupts_row_during_scan() {
+        // LD BC,FE10 / DEC BC
+        let (oam, corrupted) = run_with_oam(&[0x01, 0x10, 0xFE, 0x0B]);
+
+        assert_row_corrupted(&oam, &corrupted);
+    }
+
+    #[test]
+    fn inc_outside_oam_leaves_it_alone() {
+        // LD HL,FD00 / INC HL
+        let (oam, corrupted) = run_with_oam(&[0x21, 0x00, 0xFD, 0x23]);
+
+        assert_eq!(oam, corrupted);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0x10; // STOP
+        rom[0x143] = 0x80; // CGB flag
+        let mut bus = Bus::new(Cartridge::from_rom(rom), Gpu::new(), None);
+        let mut cpu = Cpu::new(true, false, true);
+
+        bus.write8(KEY1_ADDRESS, 0x01).unwrap();
+        cpu.next(&mut bus).unwrap();
+        assert_eq!(bus.fetch8(KEY1_ADDRESS).unwrap(), 0xFE);
+
+        // The CPU carries on instead of stopping
+        cpu.next(&mut bus).unwrap();
+        assert_eq!(cpu.program_counter(), 0x103);
+    }
+
+    #[test]
+    fn stop_wakes_on_pressed_button() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0x10; // STOP
+        let mut bus = Bus::new(Cartridge::from_rom(rom), Gpu::new(), None);
+        let mut cpu = Cpu::new(true, false, false);
+
+        // Select the direction buttons
+        bus.write8(0xFF00, 0x20).unwrap();
+        cpu.next(&mut bus).unwrap();
+        bus.write8(INTERRUPT_REQUEST_ADDRESS, 0).unwrap();
+
+        for _ in 0..10 {
+            cpu.next(&mut bus).unwrap();
+        }
+        assert_eq!(cpu.program_counter(), 0x102);
+        assert_eq!(
+            bus.fetch8(INTERRUPT_REQUEST_ADDRESS).unwrap() & JOYPAD_BITMASK,
+            0
+        );
+
+        bus.buttons.set_down(true);
+        cpu.next(&mut bus).unwrap();
+        assert_ne!(
+            bus.fetch8(INTERRUPT_REQUEST_ADDRESS).unwrap() & JOYPAD_BITMASK,
+            0
+        );
+
+        cpu.next(&mut bus).unwrap();
+        assert_eq!(cpu.program_counter(), 0x103);
+    }
+}
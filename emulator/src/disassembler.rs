@@ -57,8 +57,9 @@ impl Disassembler {
 }
 
 pub fn disassemble_instruction(i: Instruction, bus: &mut Bus, pc: u16) -> String {
-    return match i {
+    match i {
         Instruction::NOP => String::from("NOP"),
+        Instruction::STOP => String::from("STOP"),
         Instruction::EI => String::from("EI"),
         Instruction::DI => String::from("DI"),
         Instruction::RETI => String::from("RETI"),
@@ -254,7 +255,7 @@ pub fn disassemble_instruction(i: Instruction, bus: &mut Bus, pc: u16) -> String
             format!("LD {}, {}", target_string, source_string)
         }
         _ => String::from(""),
-    };
+    }
 }
 
 pub fn disassemble_prefix_instruction(
@@ -263,7 +264,7 @@ pub fn disassemble_prefix_instruction(
     bus: &mut Bus,
     pc: u16,
 ) -> String {
-    return match instruction {
+    match instruction {
         Instruction::SWAP => {
             let target_string = get_arithmetic_byte_target_string(target, bus, pc);
 
@@ -290,7 +291,7 @@ pub fn disassemble_prefix_instruction(
             format!("BIT {}, {}", n, target_string)
         }
         _ => String::from(""),
-    };
+    }
 }
 
 fn get_arithmetic_byte_target_string(
@@ -0,0 +1,298 @@
+use crate::{bus::FetchWrite, register::Register8};
+
+/// Number of bytes copied into OAM by a single transfer.
+const OAM_DMA_LENGTH: u8 = 0xA0;
+
+/// M-cycles between the write to the DMA register and the first byte
+/// being copied.
+const OAM_DMA_STARTUP_CYCLES: u8 = 1;
+
+struct PendingTransfer {
+    source: u16,
+    delay: u8,
+}
+
+/// OAM DMA controller. Copies 160 bytes from `XX00-XX9F` to OAM, one byte
+/// per M-cycle, after a short startup delay. Writing the register while a
+/// transfer is running restarts it from the new source; the old transfer
+/// keeps going until the new one has started.
+pub struct OamDma {
+    register: Register8,
+    source: u16,
+    index: u8,
+    active: bool,
+    pending: Option<PendingTransfer>,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        OamDma {
+            register: 0xFF,
+            source: 0,
+            index: 0,
+            active: false,
+            pending: None,
+        }
+    }
+
+    /// Whether a transfer currently owns the bus.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Advances the controller by one M-cycle. Returns the source address
+    /// and OAM offset of the byte to copy in this cycle, if any.
+    pub fn next(&mut self) -> Option<(u16, u8)> {
+        if let Some(pending) = self.pending.as_mut() {
+            if pending.delay == 0 {
+                self.source = pending.source;
+                self.index = 0;
+                self.active = true;
+                self.pending = None;
+            } else {
+                pending.delay -= 1;
+            }
+        }
+
+        if !self.active {
+            return None;
+        }
+
+        let transfer = (self.source + self.index as u16, self.index);
+        self.index += 1;
+
+        if self.index == OAM_DMA_LENGTH {
+            self.active = false;
+        }
+
+        Some(transfer)
+    }
+
+    fn start(&mut self, value: u8) {
+        // Sources above the WRAM echo area still read from WRAM.
+        let source = if value >= 0xE0 {
+            ((value - 0x20) as u16) << 8
+        } else {
+            (value as u16) << 8
+        };
+
+        self.pending = Some(PendingTransfer {
+            source,
+            delay: OAM_DMA_STARTUP_CYCLES,
+        });
+    }
+}
+
+impl FetchWrite for OamDma {
+    fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        self.register.fetch8(address)
+    }
+
+    fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+
+    fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        self.register.write8(address, value)?;
+        self.start(value);
+
+        Ok(())
+    }
+
+    fn write16(&mut self, _: u16, _: u16) -> std::io::Result<()> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+}
+
+const HDMA_BLOCK_LEN: u16 = 0x10;
+
+const HDMA1_ADDRESS: u16 = 0xFF51;
+const HDMA2_ADDRESS: u16 = 0xFF52;
+const HDMA3_ADDRESS: u16 = 0xFF53;
+const HDMA4_ADDRESS: u16 = 0xFF54;
+const HDMA5_ADDRESS: u16 = 0xFF55;
+
+const HDMA_HBLANK_MODE_BITMASK: u8 = 1 << 7;
+const HDMA_LENGTH_BITMASK: u8 = 0x7F;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HdmaMode {
+    Inactive,
+    GeneralPurpose,
+    HBlank,
+}
+
+/// CGB VRAM DMA. General purpose transfers copy everything at once, HBlank
+/// transfers copy one 16 byte block at the start of every HBlank.
+pub struct Hdma {
+    source: u16,
+    destination: u16,
+    /// Remaining blocks minus one, as read back from HDMA5
+    length: u8,
+    mode: HdmaMode,
+    cgb: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0,
+            length: HDMA_LENGTH_BITMASK,
+            mode: HdmaMode::Inactive,
+            cgb: false,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    /// Returns the source and VRAM destination of the next 16 byte block to
+    /// copy, if the running transfer wants one now.
+    pub fn next_block(&mut self, hblank_started: bool) -> Option<(u16, u16)> {
+        match self.mode {
+            HdmaMode::Inactive => return None,
+            HdmaMode::HBlank if !hblank_started => return None,
+            _ => {}
+        }
+
+        let block = (self.source, 0x8000 | self.destination);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_LEN);
+        self.destination = (self.destination + HDMA_BLOCK_LEN) & 0x1FF0;
+
+        if self.length == 0 {
+            self.length = HDMA_LENGTH_BITMASK;
+            self.mode = HdmaMode::Inactive;
+        } else {
+            self.length -= 1;
+        }
+
+        Some(block)
+    }
+}
+
+impl FetchWrite for Hdma {
+    fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        if !self.cgb || address != HDMA5_ADDRESS {
+            return Ok(0xFF);
+        }
+
+        let inactive = match self.mode {
+            HdmaMode::Inactive => HDMA_HBLANK_MODE_BITMASK,
+            _ => 0,
+        };
+
+        Ok(self.length | inactive)
+    }
+
+    fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+
+    fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        if !self.cgb {
+            return Ok(());
+        }
+
+        match address {
+            HDMA1_ADDRESS => self.source = (self.source & 0x00FF) | ((value as u16) << 8),
+            HDMA2_ADDRESS => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            HDMA3_ADDRESS => {
+                self.destination = (self.destination & 0x00FF) | (((value & 0x1F) as u16) << 8)
+            }
+            HDMA4_ADDRESS => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            HDMA5_ADDRESS => {
+                let hblank_mode = value & HDMA_HBLANK_MODE_BITMASK != 0;
+
+                if self.mode == HdmaMode::HBlank && !hblank_mode {
+                    // Writing bit 7 = 0 stops a running HBlank transfer
+                    self.mode = HdmaMode::Inactive;
+                    return Ok(());
+                }
+
+                self.length = value & HDMA_LENGTH_BITMASK;
+                self.mode = if hblank_mode {
+                    HdmaMode::HBlank
+                } else {
+                    HdmaMode::GeneralPurpose
+                };
+            }
+            _ => panic!("Accessing unsupported HDMA address: {:#X}", address),
+        }
+
+        Ok(())
+    }
+
+    fn write16(&mut self, _: u16, _: u16) -> std::io::Result<()> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hdma(length: u8) -> Hdma {
+        let mut hdma = Hdma::new();
+        hdma.set_cgb_mode(true);
+        hdma.write8(HDMA1_ADDRESS, 0xC1).unwrap();
+        hdma.write8(HDMA2_ADDRESS, 0x2F).unwrap();
+        hdma.write8(HDMA3_ADDRESS, 0xE8).unwrap();
+        hdma.write8(HDMA4_ADDRESS, 0x4A).unwrap();
+        hdma.write8(HDMA5_ADDRESS, length).unwrap();
+
+        hdma
+    }
+
+    #[test]
+    fn general_purpose_copies_all_blocks_at_once() {
+        let mut hdma = hdma(0x02);
+
+        // Low source and destination bits are ignored, the destination
+        // is always in VRAM
+        assert_eq!(hdma.next_block(false), Some((0xC120, 0x8840)));
+        assert_eq!(hdma.next_block(false), Some((0xC130, 0x8850)));
+        assert_eq!(hdma.next_block(false), Some((0xC140, 0x8860)));
+        assert_eq!(hdma.next_block(false), None);
+        assert_eq!(hdma.fetch8(HDMA5_ADDRESS).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn hblank_copies_one_block_per_hblank() {
+        let mut hdma = hdma(HDMA_HBLANK_MODE_BITMASK | 0x02);
+
+        assert_eq!(hdma.next_block(false), None);
+        assert_eq!(hdma.fetch8(HDMA5_ADDRESS).unwrap(), 0x02);
+
+        assert_eq!(hdma.next_block(true), Some((0xC120, 0x8840)));
+        assert_eq!(hdma.next_block(false), None);
+        assert_eq!(hdma.fetch8(HDMA5_ADDRESS).unwrap(), 0x01);
+
+        assert_eq!(hdma.next_block(true), Some((0xC130, 0x8850)));
+        assert_eq!(hdma.fetch8(HDMA5_ADDRESS).unwrap(), 0x00);
+        assert_eq!(hdma.next_block(true), Some((0xC140, 0x8860)));
+        assert_eq!(hdma.fetch8(HDMA5_ADDRESS).unwrap(), 0xFF);
+        assert_eq!(hdma.next_block(true), None);
+    }
+
+    #[test]
+    fn hblank_transfer_can_be_cancelled() {
+        let mut hdma = hdma(HDMA_HBLANK_MODE_BITMASK | 0x02);
+        hdma.next_block(true);
+
+        hdma.write8(HDMA5_ADDRESS, 0x00).unwrap();
+
+        assert_eq!(hdma.fetch8(HDMA5_ADDRESS).unwrap(), 0x81);
+        assert_eq!(hdma.next_block(true), None);
+    }
+
+    #[test]
+    fn dmg_has_no_hdma() {
+        let mut hdma = Hdma::new();
+        hdma.write8(HDMA5_ADDRESS, 0x00).unwrap();
+
+        assert_eq!(hdma.next_block(true), None);
+        assert_eq!(hdma.fetch8(HDMA5_ADDRESS).unwrap(), 0xFF);
+    }
+}
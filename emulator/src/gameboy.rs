@@ -0,0 +1,317 @@
+use std::{fs, io};
+
+use crate::{
+    bus::Bus,
+    buttons::Buttons,
+    cartridge::Cartridge,
+    constants::FRAME_CYCLES,
+    cpu::Cpu,
+    gpu::{FrameBlend, FrameBuffer, Gpu},
+    palette::{ColorCorrection, PaletteSet},
+    spu::Spu,
+};
+
+#[derive(Clone, Default)]
+pub struct GameBoyOptions {
+    /// Run this boot ROM first instead of starting the game right away
+    pub boot_rom: Option<String>,
+    /// Print each executed instruction
+    pub disassemble: bool,
+    /// Run as Super Game Boy if the game supports it, see
+    /// `Cartridge::supports_sgb`
+    pub sgb: bool,
+    /// DMG palettes, `None` for the CGB boot ROM palette of the game
+    pub palettes: Option<PaletteSet>,
+    pub color_correction: ColorCorrection,
+    pub frame_blend: FrameBlend,
+}
+
+/// A complete Game Boy running one game: CPU, bus and the hardware behind
+/// it, wired up the same way for every tool.
+pub struct GameBoy {
+    rom: Vec<u8>,
+    options: GameBoyOptions,
+    cpu: Cpu,
+    bus: Bus,
+    /// Cycles run past the end of the last `run_cycles`
+    overshoot: i64,
+}
+
+impl GameBoy {
+    pub fn new(rom: Vec<u8>, options: GameBoyOptions) -> Self {
+        let (cpu, bus) = build(&rom, &options);
+
+        GameBoy {
+            rom,
+            options,
+            cpu,
+            bus,
+            overshoot: 0,
+        }
+    }
+
+    pub fn load(path: &str, options: GameBoyOptions) -> io::Result<Self> {
+        Ok(GameBoy::new(fs::read(path)?, options))
+    }
+
+    /// Executes one instruction, returns the clock cycles it took at the
+    /// base clock, see `Bus::base_clock_cycles`.
+    pub fn step(&mut self) -> io::Result<u8> {
+        let cycles = self.cpu.next(&mut self.bus)?;
+
+        Ok(self.bus.base_clock_cycles(cycles))
+    }
+
+    /// Runs for the given clock cycles of the base clock, so a frame takes
+    /// the same in double speed. Instructions are not split, so a call may
+    /// run a little longer; the next one makes up for it.
+    pub fn run_cycles(&mut self, cycles: i64) -> io::Result<()> {
+        let mut elapsed = self.overshoot;
+        while elapsed < cycles {
+            elapsed += self.step()? as i64;
+        }
+        self.overshoot = elapsed - cycles;
+
+        Ok(())
+    }
+
+    /// Runs for the duration of one frame.
+    pub fn run_frame(&mut self) -> io::Result<()> {
+        self.run_cycles(FRAME_CYCLES)
+    }
+
+    /// Power cycles the Game Boy. Save RAM stays, and so does everything
+    /// attached from outside: the audio sink, recordings, the serial link,
+    /// hooks and debug options.
+    pub fn reset(&mut self) {
+        let (cpu, mut bus) = build(&self.rom, &self.options);
+        bus.cartridge_mut().load_ram(self.bus.cartridge().ram());
+
+        std::mem::swap(&mut bus.spu, &mut self.bus.spu);
+        bus.spu.reset();
+        bus.set_serial_link(self.bus.take_serial_link());
+        std::mem::swap(bus.write_hooks_mut(), self.bus.write_hooks_mut());
+        std::mem::swap(bus.gpu.hooks_mut(), self.bus.gpu.hooks_mut());
+        *bus.gpu.debug_options_mut() = *self.bus.gpu.debug_options_mut();
+
+        self.cpu = cpu;
+        self.bus = bus;
+        self.overshoot = 0;
+    }
+
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        self.bus.gpu.frame_buffer()
+    }
+
+    /// Returns the frame completed since the last call, if there is one.
+    pub fn take_frame(&mut self) -> Option<&FrameBuffer> {
+        self.bus.gpu.take_frame()
+    }
+
+    pub fn buttons_mut(&mut self) -> &mut Buttons {
+        &mut self.bus.buttons
+    }
+
+    pub fn spu(&self) -> &Spu {
+        &self.bus.spu
+    }
+
+    pub fn spu_mut(&mut self) -> &mut Spu {
+        &mut self.bus.spu
+    }
+
+    pub fn save_ram(&self) -> &[u8] {
+        self.bus.cartridge().ram()
+    }
+
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        self.bus.cartridge_mut().load_ram(data);
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    /// Splits the Game Boy into its CPU and bus, e.g. to link it with
+    /// another one.
+    pub fn into_parts(self) -> (Cpu, Bus) {
+        (self.cpu, self.bus)
+    }
+}
+
+fn build(rom: &[u8], options: &GameBoyOptions) -> (Cpu, Bus) {
+    let cartridge = Cartridge::from_rom(rom.to_vec());
+
+    let mut gpu = Gpu::new();
+    let palettes = options
+        .palettes
+        .unwrap_or_else(|| PaletteSet::for_cartridge(&cartridge));
+    gpu.set_dmg_palettes(palettes);
+    gpu.set_color_correction(options.color_correction);
+    gpu.set_frame_blend(options.frame_blend);
+
+    let mut bus = Bus::new(cartridge, gpu, options.boot_rom.clone());
+    if options.sgb {
+        bus.enable_sgb();
+    }
+
+    let skip_boot = options.boot_rom.is_none();
+    let cpu = Cpu::new(skip_boot, options.disassemble, bus.is_cgb_mode());
+
+    (cpu, bus)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bus::FetchWrite, link::link_cable, serial::SerialLink, spu::BufferSink};
+
+    use super::*;
+
+    const CGB_FLAG_ADDRESS: usize = 0x143;
+    const SGB_FLAG_ADDRESS: usize = 0x146;
+    const OLD_LICENSEE_CODE_ADDRESS: usize = 0x14B;
+    const VBK_ADDRESS: u16 = 0xFF4F;
+    const KEY1_ADDRESS: u16 = 0xFF4D;
+    const CURRENT_SPEED_BITMASK: u8 = 1 << 7;
+
+    /// Nothing but NOPs, 4 cycles each.
+    fn nop_rom() -> Vec<u8> {
+        vec![0; 0x8000]
+    }
+
+    /// A CGB compatible game with SGB functions that stores A to `C000`.
+    fn cgb_and_sgb_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // LD (C000),A / JR -2
+        rom[0x100..0x105].copy_from_slice(&[0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+        rom[CGB_FLAG_ADDRESS] = 0x80;
+        rom[SGB_FLAG_ADDRESS] = 0x03;
+        rom[OLD_LICENSEE_CODE_ADDRESS] = 0x33;
+
+        rom
+    }
+
+    /// A CGB game that switches to double speed and loops.
+    fn double_speed_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // LD A,1 / LDH (KEY1),A / STOP / JR -2
+        rom[0x100..0x108].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x18, 0xFE]);
+        rom[CGB_FLAG_ADDRESS] = 0x80;
+
+        rom
+    }
+
+    fn dots(game_boy: &GameBoy) -> i64 {
+        game_boy.bus().gpu.hook_context().cycles as i64
+    }
+
+    fn a_register(game_boy: &mut GameBoy) -> u8 {
+        game_boy.step().unwrap();
+        game_boy.bus_mut().fetch8(0xC000).unwrap()
+    }
+
+    #[test]
+    fn runs_cgb_games_in_cgb_mode() {
+        let mut game_boy = GameBoy::new(cgb_and_sgb_rom(), GameBoyOptions::default());
+
+        assert!(game_boy.bus().is_cgb_mode());
+        assert_eq!(a_register(&mut game_boy), 0x11);
+    }
+
+    #[test]
+    fn sgb_runs_cgb_games_in_dmg_mode() {
+        let options = GameBoyOptions {
+            sgb: true,
+            ..GameBoyOptions::default()
+        };
+        let mut game_boy = GameBoy::new(cgb_and_sgb_rom(), options);
+
+        assert!(!game_boy.bus().is_cgb_mode());
+        assert_eq!(a_register(&mut game_boy), 0x01);
+        assert_eq!(game_boy.bus_mut().fetch8(VBK_ADDRESS).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn frame_takes_as_long_in_double_speed() {
+        let mut game_boy = GameBoy::new(double_speed_rom(), GameBoyOptions::default());
+        for _ in 0..3 {
+            game_boy.step().unwrap();
+        }
+        let key1 = game_boy.bus_mut().fetch8(KEY1_ADDRESS).unwrap();
+        assert_ne!(key1 & CURRENT_SPEED_BITMASK, 0);
+
+        let start = dots(&game_boy);
+        for _ in 0..3 {
+            game_boy.run_frame().unwrap();
+        }
+
+        let elapsed = dots(&game_boy) - start;
+        assert!((elapsed - 3 * FRAME_CYCLES).abs() <= 4, "{} dots", elapsed);
+    }
+
+    #[test]
+    fn step_returns_instruction_cycles() {
+        let mut game_boy = GameBoy::new(nop_rom(), GameBoyOptions::default());
+
+        assert_eq!(game_boy.step().unwrap(), 4);
+        assert_eq!(dots(&game_boy), 4);
+    }
+
+    #[test]
+    fn run_cycles_carries_overshoot_over() {
+        let mut game_boy = GameBoy::new(nop_rom(), GameBoyOptions::default());
+
+        // Two instructions, 3 cycles too many
+        game_boy.run_cycles(5).unwrap();
+        assert_eq!(dots(&game_boy), 8);
+
+        // The overshoot counts towards the next call
+        game_boy.run_cycles(3).unwrap();
+        assert_eq!(dots(&game_boy), 8);
+
+        game_boy.run_cycles(5).unwrap();
+        assert_eq!(dots(&game_boy), 16);
+    }
+
+    #[test]
+    fn reset_keeps_save_ram() {
+        let mut game_boy = GameBoy::new(nop_rom(), GameBoyOptions::default());
+        game_boy.bus_mut().write8(0xA000, 0x42).unwrap();
+        game_boy.bus_mut().write8(0xC000, 0x42).unwrap();
+        game_boy.run_frame().unwrap();
+
+        game_boy.reset();
+
+        assert_eq!(game_boy.save_ram()[0], 0x42);
+        assert_eq!(game_boy.bus_mut().fetch8(0xC000).unwrap(), 0);
+        assert_eq!(dots(&game_boy), 0);
+    }
+
+    #[test]
+    fn reset_keeps_sink_and_link() {
+        let mut game_boy = GameBoy::new(nop_rom(), GameBoyOptions::default());
+        let sink = BufferSink::new();
+        let buffer = sink.buffer();
+        game_boy.spu_mut().set_sink(Box::new(sink), 48000);
+        game_boy.spu_mut().set_channel_muted(2, true);
+
+        let (end, mut peer) = link_cable();
+        game_boy.bus_mut().set_serial_link(Some(Box::new(end)));
+
+        game_boy.reset();
+
+        game_boy.run_frame().unwrap();
+        game_boy.spu_mut().flush_samples();
+        assert!(!buffer.take().is_empty());
+        assert!(game_boy.spu().is_channel_muted(2));
+
+        // Start an internal clock transfer, its first bit reaches the peer
+        game_boy.bus_mut().write8(0xFF02, 0x81).unwrap();
+        game_boy.run_cycles(1024).unwrap();
+        assert!(peer.poll(Some(0)).is_some());
+    }
+}
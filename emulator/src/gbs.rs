@@ -0,0 +1,372 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+};
+
+use crate::{
+    bus::{Bus, FetchWrite},
+    cartridge::Cartridge,
+    constants::FRAME_CYCLES,
+    cpu::Cpu,
+    gpu::Gpu,
+    spu::Spu,
+};
+
+const GBS_MAGIC: &[u8] = b"GBS";
+const GBS_HEADER_LEN: usize = 0x70;
+const GBS_STRING_LEN: usize = 32;
+
+/// Code below this address belongs to the player, GBS data must not be
+/// loaded there.
+const MIN_LOAD_ADDRESS: u16 = 0x400;
+
+const ROM_BANK_LEN: usize = 0x4000;
+
+const CGB_FLAG_ADDRESS: usize = 0x143;
+const CGB_FLAG: u8 = 0x80;
+
+/// The player's routines return here. Nothing is executed at this address,
+/// the CPU idles until the next call.
+const IDLE_ADDRESS: u16 = 0x0080;
+const JR_OPCODE: u8 = 0x18;
+const JP_OPCODE: u8 = 0xC3;
+const RETI_OPCODE: u8 = 0xD9;
+
+const INTERRUPT_VECTOR_ADDRESSES: [u16; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
+
+const TMA_REGISTER_ADDRESS: u16 = 0xFF06;
+const TAC_REGISTER_ADDRESS: u16 = 0xFF07;
+const KEY1_REGISTER_ADDRESS: u16 = 0xFF4D;
+const NR50_ADDRESS: u16 = 0xFF24;
+const NR51_ADDRESS: u16 = 0xFF25;
+const NR52_ADDRESS: u16 = 0xFF26;
+
+const TAC_ENABLE_BITMASK: u8 = 1 << 2;
+const TAC_CLOCK_BITMASK: u8 = 0b11;
+/// GBS extension: the play routine runs at CGB double speed
+const TAC_DOUBLE_SPEED_BITMASK: u8 = 1 << 7;
+
+/// Header of a Game Boy Sound System file.
+#[derive(Clone, Debug)]
+pub struct GbsHeader {
+    pub version: u8,
+    pub song_count: u8,
+    /// First song to play, counting from 1
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+impl GbsHeader {
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message);
+
+        if data.len() < GBS_HEADER_LEN || &data[..3] != GBS_MAGIC {
+            return Err(invalid("Not a GBS file"));
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let string = |offset: usize| read_string(&data[offset..offset + GBS_STRING_LEN]);
+
+        let header = GbsHeader {
+            version: data[0x03],
+            song_count: data[0x04],
+            first_song: data[0x05],
+            load_address: word(0x06),
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: data[0x0E],
+            timer_control: data[0x0F],
+            title: string(0x10),
+            author: string(0x30),
+            copyright: string(0x50),
+        };
+
+        if header.load_address < MIN_LOAD_ADDRESS {
+            return Err(invalid("GBS load address is too low"));
+        }
+
+        Ok(header)
+    }
+
+    /// Whether the CPU runs at CGB double speed.
+    pub fn double_speed(&self) -> bool {
+        self.timer_control & TAC_DOUBLE_SPEED_BITMASK != 0
+    }
+
+    /// Clock cycles between two calls of the play routine: the timer
+    /// period if the timer is enabled, VBlank otherwise.
+    pub fn play_period(&self) -> i64 {
+        if self.timer_control & TAC_ENABLE_BITMASK == 0 {
+            return FRAME_CYCLES;
+        }
+
+        let input_period = match self.timer_control & TAC_CLOCK_BITMASK {
+            0b00 => 1024,
+            0b01 => 16,
+            0b10 => 64,
+            _ => 256,
+        };
+        let period = (256 - self.timer_modulo as i64) * input_period;
+
+        if self.double_speed() {
+            period / 2
+        } else {
+            period
+        }
+    }
+}
+
+/// Builds a ROM image with the GBS data at its load address. The player's
+/// area below holds the RST vectors, which GBS files expect relative to the
+/// load address, and interrupt handlers that return immediately. Double
+/// speed needs a CGB, so the ROM is marked as a CGB game for it.
+fn build_rom(header: &GbsHeader, data: &[u8]) -> Vec<u8> {
+    let end = header.load_address as usize + data.len();
+    let len = end.div_ceil(ROM_BANK_LEN).max(2) * ROM_BANK_LEN;
+
+    let mut rom = vec![0xFF; len];
+    rom[header.load_address as usize..end].copy_from_slice(data);
+
+    for rst in (0x00..0x40).step_by(8) {
+        let target = header.load_address + rst as u16;
+        rom[rst..rst + 3].copy_from_slice(&[JP_OPCODE, target as u8, (target >> 8) as u8]);
+    }
+    for vector in INTERRUPT_VECTOR_ADDRESSES {
+        rom[vector as usize] = RETI_OPCODE;
+    }
+    rom[IDLE_ADDRESS as usize..IDLE_ADDRESS as usize + 2].copy_from_slice(&[JR_OPCODE, 0xFE]);
+    if header.double_speed() {
+        rom[CGB_FLAG_ADDRESS] = CGB_FLAG;
+    }
+
+    rom
+}
+
+/// Plays GBS files on the CPU, timer and APU. The init routine is called
+/// when a song starts, then the play routine at the rate of VBlank or of
+/// the timer.
+pub struct GbsPlayer {
+    header: GbsHeader,
+    cpu: Cpu,
+    bus: Bus,
+    play_period: i64,
+    cycles_until_play: i64,
+}
+
+impl GbsPlayer {
+    pub fn load(path: &str) -> io::Result<Self> {
+        GbsPlayer::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> io::Result<Self> {
+        let header = GbsHeader::parse(file)?;
+        let cartridge = Cartridge::from_rom(build_rom(&header, &file[GBS_HEADER_LEN..]));
+        let mut bus = Bus::new(cartridge, Gpu::new(), None);
+
+        if header.double_speed() {
+            bus.write8(KEY1_REGISTER_ADDRESS, 0x01)?;
+            bus.switch_speed();
+        }
+
+        Ok(GbsPlayer {
+            play_period: header.play_period(),
+            cycles_until_play: 0,
+            cpu: Cpu::new(true, false, bus.is_cgb_mode()),
+            header,
+            bus,
+        })
+    }
+
+    pub fn header(&self) -> &GbsHeader {
+        &self.header
+    }
+
+    pub fn spu_mut(&mut self) -> &mut Spu {
+        &mut self.bus.spu
+    }
+
+    /// Restarts playback with the given song, counting from 0.
+    pub fn start_song(&mut self, song: u8) -> io::Result<()> {
+        // Silence the previous song and set up sound the way players do
+        self.bus.write8(NR52_ADDRESS, 0)?;
+        self.bus.write8(NR52_ADDRESS, 0x80)?;
+        self.bus.write8(NR51_ADDRESS, 0xFF)?;
+        self.bus.write8(NR50_ADDRESS, 0x77)?;
+
+        self.bus
+            .write8(TMA_REGISTER_ADDRESS, self.header.timer_modulo)?;
+        self.bus.write8(
+            TAC_REGISTER_ADDRESS,
+            self.header.timer_control & !TAC_DOUBLE_SPEED_BITMASK,
+        )?;
+
+        self.call(self.header.init_address, song);
+        self.cycles_until_play = self.play_period;
+
+        Ok(())
+    }
+
+    fn call(&mut self, address: u16, a: u8) {
+        // Songs are timed here, interrupts would only disturb the routines
+        self.bus.interrupts.disable_master();
+        self.cpu.call_subroutine(
+            &mut self.bus,
+            address,
+            a,
+            self.header.stack_pointer,
+            IDLE_ADDRESS,
+        );
+    }
+
+    /// Runs for the given clock cycles of the base clock, see
+    /// `Bus::base_clock_cycles`. A play routine that is due while the
+    /// previous call is still running is called once it returns.
+    pub fn run(&mut self, cycles: i64) -> io::Result<()> {
+        let mut elapsed = 0;
+
+        while elapsed < cycles {
+            let idle = self.cpu.program_counter() == IDLE_ADDRESS;
+
+            if idle && self.cycles_until_play <= 0 {
+                self.cycles_until_play += self.play_period;
+                self.call(self.header.play_address, 0);
+                continue;
+            }
+
+            let cpu_cycles = if idle {
+                self.bus.next(4);
+                4
+            } else {
+                self.cpu.next(&mut self.bus)?
+            };
+            let step = self.bus.base_clock_cycles(cpu_cycles) as i64;
+
+            elapsed += step;
+            self.cycles_until_play -= step;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOAD_ADDRESS: u16 = 0x0400;
+
+    /// A GBS file whose init routine returns right away and whose play
+    /// routine counts its calls at `C000`.
+    fn gbs_file(load_address: u16, timer_modulo: u8, timer_control: u8) -> Vec<u8> {
+        let mut file = vec![0; GBS_HEADER_LEN];
+        file[..3].copy_from_slice(GBS_MAGIC);
+        file[0x03] = 1;
+        file[0x04] = 3;
+        file[0x05] = 2;
+        file[0x06..0x08].copy_from_slice(&load_address.to_le_bytes());
+        file[0x08..0x0A].copy_from_slice(&load_address.to_le_bytes());
+        file[0x0A..0x0C].copy_from_slice(&(load_address + 1).to_le_bytes());
+        file[0x0C..0x0E].copy_from_slice(&0xDFFFu16.to_le_bytes());
+        file[0x0E] = timer_modulo;
+        file[0x0F] = timer_control;
+        file[0x10..0x15].copy_from_slice(b"Title");
+        file[0x30..0x36].copy_from_slice(b"Author");
+        file[0x50..0x54].copy_from_slice(b"2024");
+
+        // RET / LD HL,C000 / INC (HL) / RET
+        file.extend_from_slice(&[0xC9, 0x21, 0x00, 0xC0, 0x34, 0xC9]);
+
+        file
+    }
+
+    fn play_calls(player: &mut GbsPlayer) -> u8 {
+        player.bus.fetch8(0xC000).unwrap()
+    }
+
+    #[test]
+    fn parses_header() {
+        let header = GbsHeader::parse(&gbs_file(LOAD_ADDRESS, 0xC0, 0x06)).unwrap();
+
+        assert_eq!(header.version, 1);
+        assert_eq!(header.song_count, 3);
+        assert_eq!(header.first_song, 2);
+        assert_eq!(header.load_address, LOAD_ADDRESS);
+        assert_eq!(header.init_address, LOAD_ADDRESS);
+        assert_eq!(header.play_address, LOAD_ADDRESS + 1);
+        assert_eq!(header.stack_pointer, 0xDFFF);
+        assert_eq!(header.timer_modulo, 0xC0);
+        assert_eq!(header.timer_control, 0x06);
+        assert_eq!(header.title, "Title");
+        assert_eq!(header.author, "Author");
+        assert_eq!(header.copyright, "2024");
+        assert_eq!(header.play_period(), 64 * 64);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(GbsHeader::parse(&gbs_file(0x3FF, 0, 0)).is_err());
+        assert!(GbsHeader::parse(b"GBX").is_err());
+
+        let mut file = gbs_file(LOAD_ADDRESS, 0, 0);
+        file[2] = b'X';
+        assert!(GbsHeader::parse(&file).is_err());
+    }
+
+    #[test]
+    fn rst_vectors_jump_relative_to_load_address() {
+        let load_address = 0x3000;
+        let file = gbs_file(load_address, 0, 0);
+        let header = GbsHeader::parse(&file).unwrap();
+        let rom = build_rom(&header, &file[GBS_HEADER_LEN..]);
+
+        assert_eq!(rom[0x00..0x03], [JP_OPCODE, 0x00, 0x30]);
+        assert_eq!(rom[0x38..0x3B], [JP_OPCODE, 0x38, 0x30]);
+        for vector in INTERRUPT_VECTOR_ADDRESSES {
+            assert_eq!(rom[vector as usize], RETI_OPCODE);
+        }
+        assert_eq!(rom[load_address as usize..][..6], file[GBS_HEADER_LEN..]);
+        assert_eq!(rom[CGB_FLAG_ADDRESS], 0xFF);
+    }
+
+    #[test]
+    fn plays_at_vblank_rate() {
+        let mut player = GbsPlayer::from_bytes(&gbs_file(LOAD_ADDRESS, 0, 0)).unwrap();
+        player.start_song(0).unwrap();
+
+        // The first call is one period after the song starts
+        player.run(10 * FRAME_CYCLES + FRAME_CYCLES / 2).unwrap();
+
+        assert_eq!(play_calls(&mut player), 10);
+    }
+
+    #[test]
+    fn double_speed_runs_the_cpu_at_double_speed() {
+        // Timer at 256 cycles per tick, 64 ticks per call, twice as fast
+        let mut player = GbsPlayer::from_bytes(&gbs_file(LOAD_ADDRESS, 0xC0, 0x87)).unwrap();
+        assert!(player.header().double_speed());
+        assert_eq!(player.header().play_period(), 64 * 256 / 2);
+        assert_eq!(player.bus.base_clock_cycles(4), 2);
+
+        player.start_song(0).unwrap();
+        player.run(10 * 64 * 256 / 2 + 64 * 256 / 4).unwrap();
+
+        assert_eq!(play_calls(&mut player), 10);
+    }
+}
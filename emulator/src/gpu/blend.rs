@@ -0,0 +1,211 @@
+use std::{
+    io::{self, ErrorKind},
+    str::FromStr,
+};
+
+use super::{DmgColor, FrameBuffer};
+
+const DEFAULT_MIX_WEIGHT: f32 = 0.5;
+const DEFAULT_LCD_RESPONSE: f32 = 0.6;
+
+/// Post-processing of completed frames, simulating the slow LCD games use
+/// for transparency by flickering objects at 30 Hz.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum FrameBlend {
+    #[default]
+    None,
+    /// Mix each frame with the previous one. The weight is the share of the
+    /// previous frame, between 0 and 1.
+    Mix(f32),
+    /// Move each pixel only part of the way towards its new color, like
+    /// the liquid crystals. The response is the share covered per frame,
+    /// between 0 and 1.
+    LcdResponse(f32),
+}
+
+impl FromStr for FrameBlend {
+    type Err = io::Error;
+
+    /// Parses `none`, `mix[:WEIGHT]` or `lcd[:RESPONSE]`.
+    fn from_str(text: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid frame blend: {}", text),
+            )
+        };
+
+        let (name, value) = match text.split_once(':') {
+            Some((name, value)) => {
+                let value: f32 = value.parse().map_err(|_| invalid())?;
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(invalid());
+                }
+                (name, Some(value))
+            }
+            None => (text, None),
+        };
+
+        match name {
+            "none" if value.is_none() => Ok(FrameBlend::None),
+            "mix" => Ok(FrameBlend::Mix(value.unwrap_or(DEFAULT_MIX_WEIGHT))),
+            "lcd" => Ok(FrameBlend::LcdResponse(
+                value.unwrap_or(DEFAULT_LCD_RESPONSE),
+            )),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub struct FrameBlender {
+    blend: FrameBlend,
+    /// Previous frame for `Mix`, the colors on screen for `LcdResponse`
+    history: Vec<[f32; 3]>,
+}
+
+impl FrameBlender {
+    pub fn new() -> Self {
+        FrameBlender {
+            blend: FrameBlend::None,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn set_blend(&mut self, blend: FrameBlend) {
+        self.blend = blend;
+        self.history.clear();
+    }
+
+    pub fn apply(&mut self, frame: &mut FrameBuffer) {
+        if self.blend == FrameBlend::None {
+            return;
+        }
+
+        let size = frame.width() * frame.height();
+        if self.history.len() != size {
+            // Nothing to blend with yet
+            self.history = frame.pixels().iter().map(|c| to_floats(*c)).collect();
+            return;
+        }
+
+        for (index, history) in self.history.iter_mut().enumerate() {
+            let x = index % frame.width();
+            let y = index / frame.width();
+            let current = to_floats(frame.get_pixel(x, y));
+
+            let output = match self.blend {
+                FrameBlend::Mix(weight) => {
+                    let output = mix(current, *history, weight);
+                    *history = current;
+                    output
+                }
+                FrameBlend::LcdResponse(response) => {
+                    *history = mix(*history, current, response);
+                    *history
+                }
+                FrameBlend::None => current,
+            };
+
+            frame.set_pixel(
+                x,
+                y,
+                DmgColor::Rgb(
+                    output[0].round() as u8,
+                    output[1].round() as u8,
+                    output[2].round() as u8,
+                ),
+            );
+        }
+    }
+}
+
+fn to_floats(color: DmgColor) -> [f32; 3] {
+    let (r, g, b) = color.to_rgb();
+    [r as f32, g as f32, b as f32]
+}
+
+/// Moves `from` towards `to` by `amount`.
+fn mix(from: [f32; 3], to: [f32; 3], amount: f32) -> [f32; 3] {
+    [
+        from[0] + (to[0] - from[0]) * amount,
+        from[1] + (to[1] - from[1]) * amount,
+        from[2] + (to[2] - from[2]) * amount,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: DmgColor = DmgColor::Rgb(0, 0, 0);
+    const ORANGE: DmgColor = DmgColor::Rgb(200, 100, 40);
+    const GREY: DmgColor = DmgColor::Rgb(60, 60, 60);
+
+    /// Blends a 2x1 frame whose left pixel changes color, the right one
+    /// stays grey. Returns the left pixel of each blended frame.
+    fn blend(blend: FrameBlend, left: &[DmgColor]) -> Vec<DmgColor> {
+        let mut blender = FrameBlender::new();
+        blender.set_blend(blend);
+
+        left.iter()
+            .map(|&color| {
+                let mut frame = FrameBuffer::new(2, 1);
+                frame.set_pixel(0, 0, color);
+                frame.set_pixel(1, 0, GREY);
+                blender.apply(&mut frame);
+
+                assert_eq!(frame.get_pixel(1, 0), GREY);
+                frame.get_pixel(0, 0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mix_blends_with_previous_frame() {
+        assert_eq!(
+            blend(FrameBlend::Mix(0.25), &[BLACK, ORANGE, ORANGE, BLACK]),
+            [
+                BLACK,
+                DmgColor::Rgb(150, 75, 30),
+                ORANGE,
+                DmgColor::Rgb(50, 25, 10)
+            ]
+        );
+    }
+
+    #[test]
+    fn lcd_response_approaches_new_color() {
+        assert_eq!(
+            blend(
+                FrameBlend::LcdResponse(0.5),
+                &[BLACK, ORANGE, ORANGE, ORANGE]
+            ),
+            [
+                BLACK,
+                DmgColor::Rgb(100, 50, 20),
+                DmgColor::Rgb(150, 75, 30),
+                DmgColor::Rgb(175, 88, 35)
+            ]
+        );
+    }
+
+    #[test]
+    fn none_leaves_frames_alone() {
+        assert_eq!(
+            blend(FrameBlend::None, &[BLACK, ORANGE, BLACK]),
+            [BLACK, ORANGE, BLACK]
+        );
+    }
+
+    #[test]
+    fn parses_blend_names() {
+        assert_eq!("none".parse::<FrameBlend>().unwrap(), FrameBlend::None);
+        assert_eq!("mix".parse::<FrameBlend>().unwrap(), FrameBlend::Mix(0.5));
+        assert_eq!(
+            "lcd:0.25".parse::<FrameBlend>().unwrap(),
+            FrameBlend::LcdResponse(0.25)
+        );
+        assert!("mix:1.5".parse::<FrameBlend>().is_err());
+        assert!("none:0.5".parse::<FrameBlend>().is_err());
+    }
+}
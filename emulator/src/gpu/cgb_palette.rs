@@ -0,0 +1,107 @@
+use crate::bus::FetchWrite;
+
+const PALETTE_RAM_LEN: usize = 64;
+
+const INDEX_BITMASK: u8 = 0x3F;
+const AUTO_INCREMENT_BITMASK: u8 = 1 << 7;
+const UNUSED_BITMASK: u8 = 1 << 6;
+
+/// CGB color palette memory, accessed through an index register
+/// (BCPS/OCPS, even address) and a data register (BCPD/OCPD, odd address).
+/// Holds eight palettes of four RGB555 colors each.
+pub struct CgbPalette {
+    index: u8,
+    auto_increment: bool,
+    data: [u8; PALETTE_RAM_LEN],
+}
+
+impl CgbPalette {
+    pub fn new() -> Self {
+        CgbPalette {
+            index: 0,
+            auto_increment: false,
+            data: [0xFF; PALETTE_RAM_LEN],
+        }
+    }
+
+    pub fn get_rgb555(&self, palette: u8, color: u8) -> u16 {
+        let offset = (palette as usize * 4 + color as usize) * 2;
+
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+}
+
+impl FetchWrite for CgbPalette {
+    fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        if address & 1 == 0 {
+            let auto_increment = if self.auto_increment {
+                AUTO_INCREMENT_BITMASK
+            } else {
+                0
+            };
+            Ok(self.index | auto_increment | UNUSED_BITMASK)
+        } else {
+            Ok(self.data[self.index as usize])
+        }
+    }
+
+    fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+
+    fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        if address & 1 == 0 {
+            self.index = value & INDEX_BITMASK;
+            self.auto_increment = value & AUTO_INCREMENT_BITMASK != 0;
+        } else {
+            self.data[self.index as usize] = value;
+            if self.auto_increment {
+                self.index = (self.index + 1) & INDEX_BITMASK;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write16(&mut self, _: u16, _: u16) -> std::io::Result<()> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BCPS_ADDRESS: u16 = 0xFF68;
+    const BCPD_ADDRESS: u16 = 0xFF69;
+
+    #[test]
+    fn auto_increment_wraps_around() {
+        let mut palette = CgbPalette::new();
+        palette
+            .write8(BCPS_ADDRESS, AUTO_INCREMENT_BITMASK | 0x3E)
+            .unwrap();
+        for value in [0x1F, 0x7C, 0xE0] {
+            palette.write8(BCPD_ADDRESS, value).unwrap();
+        }
+
+        assert_eq!(palette.get_rgb555(7, 3), 0x7C1F);
+        assert_eq!(palette.get_rgb555(0, 0) & 0xFF, 0xE0);
+        assert_eq!(
+            palette.fetch8(BCPS_ADDRESS).unwrap(),
+            AUTO_INCREMENT_BITMASK | UNUSED_BITMASK | 0x01
+        );
+    }
+
+    #[test]
+    fn index_stays_without_auto_increment() {
+        let mut palette = CgbPalette::new();
+        palette.write8(BCPS_ADDRESS, 0x02).unwrap();
+        palette.write8(BCPD_ADDRESS, 0x12).unwrap();
+        palette.write8(BCPD_ADDRESS, 0x34).unwrap();
+
+        assert_eq!(palette.fetch8(BCPS_ADDRESS).unwrap(), UNUSED_BITMASK | 0x02);
+        assert_eq!(palette.fetch8(BCPD_ADDRESS).unwrap(), 0x34);
+        assert_eq!(palette.get_rgb555(0, 1), 0xFF34);
+    }
+}
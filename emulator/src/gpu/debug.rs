@@ -0,0 +1,47 @@
+use super::DmgColor;
+
+/// Layer a pixel was taken from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layer {
+    Background,
+    Window,
+    Object,
+}
+
+/// Switches to isolate rendering problems. Disabled layers draw as if the
+/// game had turned them off.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DebugOptions {
+    pub background: bool,
+    pub window: bool,
+    pub objects: bool,
+    /// Only show the first 10 objects on each line, like the hardware
+    pub object_limit: bool,
+    /// Tint pixels by the layer they come from: background blue, window
+    /// green, objects red. Has no effect in SGB mode.
+    pub layer_tint: bool,
+}
+
+impl Default for DebugOptions {
+    fn default() -> Self {
+        DebugOptions {
+            background: true,
+            window: true,
+            objects: true,
+            object_limit: true,
+            layer_tint: false,
+        }
+    }
+}
+
+pub fn tint(color: DmgColor, layer: Layer) -> DmgColor {
+    let (r, g, b) = color.to_rgb();
+    let (tint_r, tint_g, tint_b) = match layer {
+        Layer::Background => (0x00, 0x00, 0xFF),
+        Layer::Window => (0x00, 0xFF, 0x00),
+        Layer::Object => (0xFF, 0x00, 0x00),
+    };
+    let mix = |color: u8, tint: u8| ((color as u16 + tint as u16) / 2) as u8;
+
+    DmgColor::Rgb(mix(r, tint_r), mix(g, tint_g), mix(b, tint_b))
+}
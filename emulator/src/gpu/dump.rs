@@ -0,0 +1,186 @@
+use super::{
+    DmgColor, FrameBuffer, Gpu, ATTR_VRAM_BANK_BITMASK, OAM_OBJECTS, TILE_DATA_BLOCK_0_ADDRESS,
+    TILE_LEN, TILE_MAP_BLOCK_0_ADDRESS, TILE_MAP_BLOCK_1_ADDRESS,
+};
+use crate::constants::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const TILES_PER_BANK: usize = 384;
+const TILE_SHEET_COLUMNS: usize = 16;
+
+const TILE_MAP_SIZE: usize = 256;
+const VIEWPORT_COLOR: DmgColor = DmgColor::Rgb(0xFF, 0x00, 0x00);
+
+const OAM_TABLE_COLUMNS: usize = 2;
+const OAM_ENTRY_WIDTH: usize = 72;
+const OAM_ENTRY_HEIGHT: usize = 18;
+const OAM_TEXT_X: usize = 10;
+const OAM_TEXT_Y: usize = 6;
+const OAM_BACKGROUND: DmgColor = DmgColor::Rgb(0xC0, 0xC0, 0xC0);
+const OAM_TEXT_COLOR: DmgColor = DmgColor::Rgb(0x00, 0x00, 0x00);
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const GLYPH_ADVANCE: usize = 4;
+
+/// 3x5 pixel hex digits, rows from top to bottom, most significant bit on
+/// the left.
+const HEX_GLYPHS: [u16; 16] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+    0b010_101_111_101_101,
+    0b110_101_110_101_110,
+    0b011_100_100_100_011,
+    0b110_101_101_101_110,
+    0b111_100_111_100_111,
+    0b111_100_111_100_100,
+];
+
+fn shade(color: u8) -> DmgColor {
+    match color {
+        0 => DmgColor::White,
+        1 => DmgColor::LightGrey,
+        2 => DmgColor::DarkGrey,
+        _ => DmgColor::Black,
+    }
+}
+
+fn draw_hex(frame: &mut FrameBuffer, x: usize, y: usize, value: u8) {
+    for (digit, nibble) in [value >> 4, value & 0xF].into_iter().enumerate() {
+        let glyph = HEX_GLYPHS[nibble as usize];
+
+        for row in 0..GLYPH_HEIGHT {
+            for column in 0..GLYPH_WIDTH {
+                let bit = (GLYPH_HEIGHT - 1 - row) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - column);
+                if glyph & (1 << bit) != 0 {
+                    let x = x + digit * GLYPH_ADVANCE + column;
+                    frame.set_pixel(x, y + row, OAM_TEXT_COLOR);
+                }
+            }
+        }
+    }
+}
+
+/// Debug views of the PPU memory, used to track down graphics corruption.
+impl Gpu {
+    /// Decodes all tiles of VRAM into a sheet of 16 tiles per row, in plain
+    /// shades. On CGB, bank 1 is placed right of bank 0.
+    pub fn dump_tiles(&self) -> FrameBuffer {
+        let banks = if self.cgb { 2 } else { 1 };
+        let bank_width = TILE_SHEET_COLUMNS * 8;
+        let mut frame =
+            FrameBuffer::new(bank_width * banks, TILES_PER_BANK / TILE_SHEET_COLUMNS * 8);
+
+        for bank in 0..banks {
+            for tile in 0..TILES_PER_BANK {
+                let tile_address = TILE_DATA_BLOCK_0_ADDRESS + (tile as u16 * TILE_LEN as u16);
+                let tile_x = bank * bank_width + (tile % TILE_SHEET_COLUMNS) * 8;
+                let tile_y = (tile / TILE_SHEET_COLUMNS) * 8;
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let color = self.get_tile_pixel(bank as u8, tile_address, x, y);
+                        frame.set_pixel(tile_x + x as usize, tile_y + y as usize, shade(color));
+                    }
+                }
+            }
+        }
+
+        frame
+    }
+
+    /// Renders the tile map at 9800 (`high == false`) or 9C00 with the
+    /// current background palettes, and outlines the area SCX/SCY show.
+    pub fn dump_tile_map(&self, high: bool) -> FrameBuffer {
+        let map_address = match high {
+            true => TILE_MAP_BLOCK_1_ADDRESS,
+            false => TILE_MAP_BLOCK_0_ADDRESS,
+        };
+        let mut frame = FrameBuffer::new(TILE_MAP_SIZE, TILE_MAP_SIZE);
+
+        for y in 0..TILE_MAP_SIZE {
+            for x in 0..TILE_MAP_SIZE {
+                let (color, attributes) = self.get_map_pixel(map_address, x as u8, y as u8);
+                let color = if self.cgb {
+                    self.get_bg_color(color, attributes)
+                } else {
+                    self.get_dmg_palette_color(self.bgp, color, &self.dmg_palettes.bg)
+                };
+                frame.set_pixel(x, y, color);
+            }
+        }
+
+        let left = self.scx as usize;
+        let top = self.scy as usize;
+        let right = left + SCREEN_WIDTH - 1;
+        let bottom = top + SCREEN_HEIGHT - 1;
+        let wrap = |position: usize| position % TILE_MAP_SIZE;
+
+        for x in left..=right {
+            frame.set_pixel(wrap(x), wrap(top), VIEWPORT_COLOR);
+            frame.set_pixel(wrap(x), wrap(bottom), VIEWPORT_COLOR);
+        }
+        for y in top..=bottom {
+            frame.set_pixel(wrap(left), wrap(y), VIEWPORT_COLOR);
+            frame.set_pixel(wrap(right), wrap(y), VIEWPORT_COLOR);
+        }
+
+        frame
+    }
+
+    /// Lists all 40 objects with a preview of their tiles, followed by
+    /// index, Y, X, tile and attributes in hex.
+    pub fn dump_oam(&self) -> FrameBuffer {
+        let rows = OAM_OBJECTS / OAM_TABLE_COLUMNS;
+        let mut frame =
+            FrameBuffer::new(OAM_ENTRY_WIDTH * OAM_TABLE_COLUMNS, OAM_ENTRY_HEIGHT * rows);
+        frame.clear(OAM_BACKGROUND);
+
+        let obj_height = if self.lcdc.get_obj_size() { 16 } else { 8 };
+
+        for object in 0..OAM_OBJECTS {
+            let entry = &self.oam.buffer[object * 4..object * 4 + 4];
+            let (y_position, x_position, tile_index, attributes) =
+                (entry[0], entry[1], entry[2], entry[3]);
+            let entry_x = (object / rows) * OAM_ENTRY_WIDTH;
+            let entry_y = (object % rows) * OAM_ENTRY_HEIGHT + 1;
+
+            let tile_index = if obj_height == 16 {
+                tile_index & 0xFE
+            } else {
+                tile_index
+            };
+            let bank = (self.cgb && attributes & ATTR_VRAM_BANK_BITMASK != 0) as u8;
+            let tile_address = TILE_DATA_BLOCK_0_ADDRESS + (tile_index as u16 * TILE_LEN as u16);
+
+            for y in 0..obj_height {
+                for x in 0..8 {
+                    // Rows of the second tile follow the first one directly
+                    let color = self.get_tile_pixel(bank, tile_address, x, y);
+                    if color != 0 {
+                        let color = self.get_obj_color(color, attributes);
+                        frame.set_pixel(entry_x + 1 + x as usize, entry_y + y as usize, color);
+                    }
+                }
+            }
+
+            let text_y = entry_y + OAM_TEXT_Y - 1;
+            for (column, value) in [object as u8, y_position, x_position, tile_index, attributes]
+                .into_iter()
+                .enumerate()
+            {
+                let text_x = entry_x + OAM_TEXT_X + column * 3 * GLYPH_ADVANCE;
+                draw_hex(&mut frame, text_x, text_y, value);
+            }
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use super::DmgColor;
+
+/// A completed (or in progress) picture, stored row by row.
+#[derive(Clone)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<DmgColor>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![DmgColor::White; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[DmgColor] {
+        &self.pixels
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> DmgColor {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: DmgColor) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    pub fn clear(&mut self, color: DmgColor) {
+        self.pixels.fill(color);
+    }
+
+    /// Writes the frame as an 8 bit RGB PNG file.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|color| {
+                let (r, g, b) = color.to_rgb();
+                [r, g, b]
+            })
+            .collect();
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+
+        Ok(())
+    }
+}
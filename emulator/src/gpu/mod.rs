@@ -3,36 +3,64 @@ use std::io;
 
 use crate::{
     bus::FetchWrite,
-    constants::{OAM_END_ADDRESS, OAM_START_ADDRESS, VRAM_END_ADDRESS, VRAM_START_ADDRESS},
+    constants::{
+        OAM_END_ADDRESS, OAM_START_ADDRESS, SCREEN_HEIGHT, SCREEN_WIDTH, VRAM_END_ADDRESS,
+        VRAM_START_ADDRESS,
+    },
+    hooks::{GpuHooks, HookContext},
     interrupts::Interrupts,
+    palette::{ColorCorrection, Palette, PaletteSet, GREY},
     ram::Ram,
     register::Register8,
+    sgb::{Sgb, SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH, TRANSFER_LEN},
 };
 
-use self::registers::{Mode, LCDC, STAT};
+use self::{
+    blend::FrameBlender,
+    cgb_palette::CgbPalette,
+    registers::{LCDC, STAT, VBK},
+};
+
+pub use self::{
+    blend::FrameBlend,
+    debug::{DebugOptions, Layer},
+    frame::FrameBuffer,
+    registers::Mode,
+};
 
+mod blend;
+mod cgb_palette;
+mod debug;
+mod dump;
+mod frame;
 mod registers;
 
 const OAM_CYCLES: u32 = 80;
 const VRAM_CYCLES: u32 = 172;
-const HBLANK_CYCLES: u32 = 204;
 const SCANLINE_CYCLES: u32 = 456;
 
 const VBLANK_START_LINE: u8 = 144;
 const VBLANK_END_LINE: u8 = 153;
 
+/// Dots into line 153 after which LY already reads 0.
+const LINE_153_LY_RESET_CYCLES: u32 = 4;
+
 const LCDC_ADDRESS: u16 = 0xFF40;
 const STAT_ADDRESS: u16 = 0xFF41;
 const SCY_ADDRESS: u16 = 0xFF42;
 const SCX_ADDRESS: u16 = 0xFF43;
 const LY_ADDRESS: u16 = 0xFF44;
 const LYC_ADDRESS: u16 = 0xFF45;
-const DMA_ADDRESS: u16 = 0xFF46;
 const BGP_ADDRESS: u16 = 0xFF47;
 const OBP0_ADDRESS: u16 = 0xFF48;
 const OBP1_ADDRESS: u16 = 0xFF49;
 const WY_ADDRESS: u16 = 0xFF4A;
 const WX_ADDRESS: u16 = 0xFF4B;
+const VBK_ADDRESS: u16 = 0xFF4F;
+const BCPS_ADDRESS: u16 = 0xFF68;
+const BCPD_ADDRESS: u16 = 0xFF69;
+const OCPS_ADDRESS: u16 = 0xFF6A;
+const OCPD_ADDRESS: u16 = 0xFF6B;
 
 const TILE_DATA_BLOCK_0_ADDRESS: u16 = 0x8000;
 const TILE_DATA_BLOCK_1_ADDRESS: u16 = 0x8800;
@@ -43,65 +71,270 @@ const TILE_MAP_BLOCK_1_ADDRESS: u16 = 0x9C00;
 
 const TILE_LEN: u8 = 16;
 
+const ATTR_PALETTE_BITMASK: u8 = 0b111;
+const ATTR_VRAM_BANK_BITMASK: u8 = 1 << 3;
+const ATTR_DMG_PALETTE_BITMASK: u8 = 1 << 4;
+const ATTR_X_FLIP_BITMASK: u8 = 1 << 5;
+const ATTR_Y_FLIP_BITMASK: u8 = 1 << 6;
+const ATTR_PRIORITY_BITMASK: u8 = 1 << 7;
+
+/// Objects the PPU can show on a single line
+const OBJECTS_PER_LINE: usize = 10;
+const OAM_OBJECTS: usize = 40;
+
+/// WX value placing the window at the left edge of the screen
+const WINDOW_X_OFFSET: u8 = 7;
+
+const OAM_ROWS: usize = 20;
+const OAM_ROW_LEN: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum DmgColor {
     White,
     LightGrey,
     DarkGrey,
     Black,
+    /// Full color output of CGB mode
+    Rgb(u8, u8, u8),
+}
+
+impl DmgColor {
+    /// Converts a 15 bit BGR color as used by CGB and SGB palettes.
+    pub fn from_rgb555(rgb555: u16) -> Self {
+        let scale = |c: u16| {
+            let c = (c & 0x1F) as u8;
+            (c << 3) | (c >> 2)
+        };
+
+        DmgColor::Rgb(scale(rgb555), scale(rgb555 >> 5), scale(rgb555 >> 10))
+    }
+
+    /// RGB value of the color. Shades are shown in grey.
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        let color = match self {
+            DmgColor::White => GREY[0],
+            DmgColor::LightGrey => GREY[1],
+            DmgColor::DarkGrey => GREY[2],
+            DmgColor::Black => GREY[3],
+            color => color,
+        };
+
+        match color {
+            DmgColor::Rgb(r, g, b) => (r, g, b),
+            _ => unreachable!(),
+        }
+    }
 }
 
 pub trait Display {
-    fn render_pixel(&mut self, x: u8, y: u8, dmg_color: DmgColor);
-    fn present(&mut self);
+    fn present(&mut self, frame: &FrameBuffer);
 }
 
-pub struct Gpu<'a> {
-    display: &'a mut dyn Display,
+pub struct Gpu {
+    /// Frame the PPU is currently drawing into
+    back_frame: FrameBuffer,
+    /// Last completed frame
+    front_frame: FrameBuffer,
+    frame_ready: bool,
+    cgb: bool,
     vram: Ram,
+    vram_bank1: Ram,
     oam: Ram,
-    modeclock: u32,
+    lcd_on: bool,
+    skip_frame: bool,
+    line: u8,
+    dot: u32,
+    stat_line: bool,
     lcdc: LCDC,
     stat: STAT,
     scy: Register8,
     scx: Register8,
     ly: Register8,
     lyc: Register8,
-    dma: Register8,
     bgp: Register8,
     obp0: Register8,
     obp1: Register8,
     wy: Register8,
     wx: Register8,
-    selected_oam_objects: [u16; 10],
+    vbk: VBK,
+    bg_palette: CgbPalette,
+    obj_palette: CgbPalette,
+    hblank_started: bool,
+    selected_oam_objects: [u16; OAM_OBJECTS],
+    /// Line of the window drawn next
+    window_line: u8,
+    window_drawn: bool,
+    debug_options: DebugOptions,
+    sgb: Option<Sgb>,
+    dmg_palettes: PaletteSet,
+    color_correction: ColorCorrection,
+    frame_blender: FrameBlender,
+    /// Dots since power on
+    cycles: u64,
+    hooks: GpuHooks,
 }
 
-impl<'a> Gpu<'a> {
-    pub fn new(display: &'a mut dyn Display) -> Self {
+impl Default for Gpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gpu {
+    pub fn new() -> Self {
         let vram: Ram = Ram::new(0x2000, VRAM_START_ADDRESS);
+        let vram_bank1: Ram = Ram::new(0x2000, VRAM_START_ADDRESS);
         let oam: Ram = Ram::new(0xA0, OAM_START_ADDRESS);
         Gpu {
-            display,
+            back_frame: FrameBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT),
+            front_frame: FrameBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT),
+            frame_ready: false,
+            cgb: false,
             vram,
+            vram_bank1,
             oam,
-            modeclock: 0,
+            lcd_on: false,
+            skip_frame: false,
+            line: 0,
+            dot: 0,
+            stat_line: false,
             lcdc: LCDC::new(),
             stat: STAT::new(),
             scy: 0,
             scx: 0,
             ly: 0,
             lyc: 0,
-            dma: 0,
             bgp: 0,
             obp0: 0,
             obp1: 0,
             wy: 0,
             wx: 0,
-            selected_oam_objects: [0x0; 10],
+            vbk: VBK::new(),
+            bg_palette: CgbPalette::new(),
+            obj_palette: CgbPalette::new(),
+            hblank_started: false,
+            selected_oam_objects: [0x0; OAM_OBJECTS],
+            window_line: 0,
+            window_drawn: false,
+            debug_options: DebugOptions::default(),
+            frame_blender: FrameBlender::new(),
+            cycles: 0,
+            hooks: GpuHooks::default(),
+            sgb: None,
+            dmg_palettes: PaletteSet::default(),
+            color_correction: ColorCorrection::None,
         }
     }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    /// Sets the colors of DMG output. The SGB colorizes frames itself.
+    pub fn set_dmg_palettes(&mut self, palettes: PaletteSet) {
+        self.dmg_palettes = palettes;
+    }
+
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        self.color_correction = color_correction;
+    }
+
+    /// Callbacks for tooling, fired at the start of each line, on mode
+    /// changes and at the start of VBlank.
+    pub fn hooks_mut(&mut self) -> &mut GpuHooks {
+        &mut self.hooks
+    }
+
+    pub fn hook_context(&self) -> HookContext {
+        HookContext {
+            ly: self.ly,
+            dot: self.dot,
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn set_frame_blend(&mut self, blend: FrameBlend) {
+        self.frame_blender.set_blend(blend);
+    }
+
+    pub fn debug_options_mut(&mut self) -> &mut DebugOptions {
+        &mut self.debug_options
+    }
+
+    /// Runs the screen through the Super Game Boy. Frames are colorized
+    /// and framed by the border, which makes them 256x224.
+    pub fn enable_sgb(&mut self) {
+        self.sgb = Some(Sgb::new());
+        self.front_frame = FrameBuffer::new(SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT);
+    }
+
+    pub fn handle_sgb_command(&mut self, command: &[u8]) {
+        let transfer = if Sgb::is_transfer_command(command) {
+            self.get_sgb_transfer_data()
+        } else {
+            Vec::new()
+        };
+
+        if let Some(sgb) = self.sgb.as_mut() {
+            sgb.handle_command(command, &transfer);
+        }
+    }
+
+    /// VRAM transfers send the tiles of the first 256 background cells,
+    /// in the order they are shown on screen.
+    fn get_sgb_transfer_data(&self) -> Vec<u8> {
+        let map_address = match self.lcdc.get_bg_area() {
+            true => TILE_MAP_BLOCK_1_ADDRESS,
+            false => TILE_MAP_BLOCK_0_ADDRESS,
+        };
+        let columns = (SCREEN_WIDTH / 8) as u16;
+        let mut data = Vec::with_capacity(TRANSFER_LEN);
+
+        for cell in 0..(TRANSFER_LEN as u16 / TILE_LEN as u16) {
+            let tile_no = (cell / columns * 32) + (cell % columns);
+            let tile_index = self.read_vram(0, map_address + tile_no);
+            let tile_address = self.get_bg_tile_address(tile_index);
+
+            for offset in 0..TILE_LEN as u16 {
+                data.push(self.read_vram(0, tile_address + offset));
+            }
+        }
+
+        data
+    }
+
+    /// Whether an HBlank period started since the last call, used to pace
+    /// HBlank HDMA.
+    pub fn take_hblank_started(&mut self) -> bool {
+        let hblank_started = self.hblank_started;
+        self.hblank_started = false;
+
+        hblank_started
+    }
+
+    /// Reads a byte from the currently selected VRAM bank on behalf of OAM
+    /// DMA, which reaches VRAM even while the PPU draws.
+    pub fn read_dma_vram(&self, address: u16) -> u8 {
+        self.read_vram(self.vbk.get_bank(), address)
+    }
+
+    /// Writes a byte into the currently selected VRAM bank on behalf of
+    /// HDMA.
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        let vram = if self.vbk.get_bank() == 0 {
+            &mut self.vram
+        } else {
+            &mut self.vram_bank1
+        };
+
+        vram.buffer[(address - VRAM_START_ADDRESS) as usize] = value;
+    }
     fn get_address_target(&mut self, address: u16) -> io::Result<&mut dyn FetchWrite> {
         match address {
-            VRAM_START_ADDRESS..=VRAM_END_ADDRESS => Ok(&mut self.vram),
+            VRAM_START_ADDRESS..=VRAM_END_ADDRESS => match self.vbk.get_bank() {
+                0 => Ok(&mut self.vram),
+                _ => Ok(&mut self.vram_bank1),
+            },
             OAM_START_ADDRESS..=OAM_END_ADDRESS => Ok(&mut self.oam),
             LCDC_ADDRESS => Ok(&mut self.lcdc),
             STAT_ADDRESS => Ok(&mut self.stat),
@@ -109,101 +342,255 @@ impl<'a> Gpu<'a> {
             SCX_ADDRESS => Ok(&mut self.scx),
             LY_ADDRESS => Ok(&mut self.ly),
             LYC_ADDRESS => Ok(&mut self.lyc),
-            DMA_ADDRESS => Ok(&mut self.dma),
             BGP_ADDRESS => Ok(&mut self.bgp),
             OBP0_ADDRESS => Ok(&mut self.obp0),
             OBP1_ADDRESS => Ok(&mut self.obp1),
             WY_ADDRESS => Ok(&mut self.wy),
             WX_ADDRESS => Ok(&mut self.wx),
+            VBK_ADDRESS => Ok(&mut self.vbk),
+            BCPS_ADDRESS | BCPD_ADDRESS => Ok(&mut self.bg_palette),
+            OCPS_ADDRESS | OCPD_ADDRESS => Ok(&mut self.obj_palette),
             _ => panic!("Address violation: {:#X}", address),
         }
     }
 
-    pub fn get_dma(&self) -> u8 {
-        self.dma
+    /// Writes a byte into OAM on behalf of the OAM DMA controller.
+    pub fn write_oam(&mut self, offset: u8, value: u8) {
+        self.oam.buffer[offset as usize] = value;
     }
 
-    pub fn next(&mut self, cycles: u8, interrupts: &mut Interrupts) {
-        self.modeclock += cycles as u32;
+    /// The PPU owns VRAM while drawing and OAM while scanning or drawing,
+    /// the CPU reads `0xFF` and its writes are dropped.
+    fn cpu_access_blocked(&self, address: u16) -> bool {
+        if !self.lcd_on {
+            return false;
+        }
 
-        match self.stat.get_mode() {
-            Mode::ScanOam => {
-                if self.modeclock >= OAM_CYCLES {
-                    self.modeclock = self.modeclock % OAM_CYCLES;
-                    self.stat.set_mode(Mode::ScanVram);
-                }
-            }
-            Mode::ScanVram => {
-                if self.modeclock >= VRAM_CYCLES {
-                    self.modeclock = self.modeclock % VRAM_CYCLES;
-                    self.stat.set_mode(Mode::HBlank);
+        matches!(
+            (address, self.stat.get_mode()),
+            (
+                VRAM_START_ADDRESS..=VRAM_END_ADDRESS | BCPD_ADDRESS | OCPD_ADDRESS,
+                Mode::ScanVram
+            ) | (
+                OAM_START_ADDRESS..=OAM_END_ADDRESS,
+                Mode::ScanOam | Mode::ScanVram
+            )
+        )
+    }
 
-                    if self.stat.get_mode_hblank_interrupt() {
-                        interrupts.set_lcd_stat_request(true);
-                    }
+    fn cgb_register_unavailable(&self, address: u16) -> bool {
+        !self.cgb && matches!(address, VBK_ADDRESS | BCPS_ADDRESS..=OCPD_ADDRESS)
+    }
 
-                    if self.stat.get_lyc_coincidence_interrupt() && self.stat.get_lyc_coincidence()
-                    {
-                        interrupts.set_lcd_stat_request(true);
-                    }
+    /// DMG OAM corruption bug: a 16 bit increment or decrement of a
+    /// register pointing into `FE00-FEFF` while the PPU is scanning OAM
+    /// garbles the row the PPU is currently reading.
+    pub fn trigger_oam_corruption(&mut self) {
+        if self.cgb || !self.lcd_on || self.stat.get_mode() != Mode::ScanOam {
+            return;
+        }
 
-                    self.stat.set_lyc_match(self.stat.get_lyc_coincidence());
-                }
+        let row = (self.dot / 4) as usize;
+        // The first row is never affected
+        if row == 0 || row >= OAM_ROWS {
+            return;
+        }
+
+        let current = row * OAM_ROW_LEN;
+        let previous = current - OAM_ROW_LEN;
+        let buffer = &mut self.oam.buffer;
+
+        let word = |offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+        let a = word(current);
+        let b = word(previous);
+        let c = word(previous + 4);
+        let corrupted = ((a ^ c) & (b ^ c)) ^ c;
+
+        buffer[current..current + 2].copy_from_slice(&corrupted.to_le_bytes());
+        for offset in 2..OAM_ROW_LEN {
+            buffer[current + offset] = buffer[previous + offset];
+        }
+    }
+
+    pub fn next(&mut self, cycles: u8, interrupts: &mut Interrupts) {
+        if !self.lcdc.get_lcd_enable() {
+            self.cycles += cycles as u64;
+            if self.lcd_on {
+                self.turn_lcd_off();
             }
-            Mode::HBlank => {
-                if self.modeclock >= HBLANK_CYCLES {
-                    self.modeclock = self.modeclock % HBLANK_CYCLES;
-
-                    self.render_line();
-                    self.ly = self.ly.wrapping_add(1);
-
-                    if self.ly == VBLANK_START_LINE {
-                        self.stat.set_mode(Mode::VBlank);
-                        self.present_image();
-
-                        interrupts.set_v_blank_request(true);
-
-                        if self.stat.get_mode_vblank_interrupt() {
-                            interrupts.set_lcd_stat_request(true);
-                        }
-                    } else {
-                        self.stat.set_mode(Mode::ScanOam);
-                        if self.stat.get_mode_oam_interrupt() {
-                            interrupts.set_lcd_stat_request(true);
-                        }
-                    }
-                }
+            return;
+        }
+
+        if !self.lcd_on {
+            self.turn_lcd_on();
+        }
+
+        for _ in 0..cycles {
+            self.next_dot(interrupts);
+        }
+    }
+
+    fn next_dot(&mut self, interrupts: &mut Interrupts) {
+        self.dot += 1;
+        self.cycles += 1;
+
+        if self.line < VBLANK_START_LINE {
+            if self.dot == OAM_CYCLES {
+                self.set_mode(Mode::ScanVram);
+            } else if self.dot == OAM_CYCLES + VRAM_CYCLES {
+                self.set_mode(Mode::HBlank);
+                self.hblank_started = true;
+                self.render_line();
             }
-            Mode::VBlank => {
-                if self.modeclock >= SCANLINE_CYCLES {
-                    self.modeclock = self.modeclock % SCANLINE_CYCLES;
-                    self.ly = self.ly.wrapping_add(1);
-
-                    if self.ly > VBLANK_END_LINE {
-                        self.stat.set_mode(Mode::ScanOam);
-                        self.ly = 0;
-
-                        if self.stat.get_mode_oam_interrupt() {
-                            interrupts.set_lcd_stat_request(true);
-                        }
-                    }
-                }
+        }
+
+        // LY already reads 0 for most of the last line.
+        if self.line == VBLANK_END_LINE && self.dot == LINE_153_LY_RESET_CYCLES {
+            self.ly = 0;
+        }
+
+        if self.dot == SCANLINE_CYCLES {
+            self.dot = 0;
+            self.next_line(interrupts);
+        }
+
+        self.stat.set_lyc_match(self.ly == self.lyc);
+        self.update_stat_line(interrupts);
+    }
+
+    fn next_line(&mut self, interrupts: &mut Interrupts) {
+        self.line += 1;
+
+        if self.line > VBLANK_END_LINE {
+            self.line = 0;
+        }
+
+        self.ly = self.line;
+
+        if self.window_drawn {
+            self.window_drawn = false;
+            self.window_line += 1;
+        }
+        if self.line == 0 {
+            self.window_line = 0;
+        }
+
+        self.call_scanline_hook();
+
+        if self.line == VBLANK_START_LINE {
+            self.set_mode(Mode::VBlank);
+            interrupts.set_v_blank_request(true);
+            self.present_image();
+
+            let context = self.hook_context();
+            if let Some(hook) = self.hooks.vblank.as_mut() {
+                hook(&context);
             }
+        } else if self.line < VBLANK_START_LINE {
+            self.set_mode(Mode::ScanOam);
+        }
+    }
+
+    fn call_scanline_hook(&mut self) {
+        let context = self.hook_context();
+        if let Some(hook) = self.hooks.scanline.as_mut() {
+            hook(&context);
         }
     }
 
+    fn set_mode(&mut self, mode: Mode) {
+        self.stat.set_mode(mode);
+
+        let context = self.hook_context();
+        if let Some(hook) = self.hooks.mode.as_mut() {
+            hook(mode, &context);
+        }
+    }
+
+    /// All STAT interrupt sources are OR'ed into a single line, the
+    /// interrupt is only requested when that line goes from low to high.
+    fn update_stat_line(&mut self, interrupts: &mut Interrupts) {
+        let mode_source = match self.stat.get_mode() {
+            Mode::HBlank => self.stat.get_mode_hblank_interrupt(),
+            Mode::VBlank => self.stat.get_mode_vblank_interrupt(),
+            Mode::ScanOam => self.stat.get_mode_oam_interrupt(),
+            Mode::ScanVram => false,
+        };
+        let lyc_source =
+            self.stat.get_lyc_coincidence_interrupt() && self.stat.get_lyc_coincidence();
+
+        let stat_line = mode_source || lyc_source;
+        if stat_line && !self.stat_line {
+            interrupts.set_lcd_stat_request(true);
+        }
+
+        self.stat_line = stat_line;
+    }
+
+    fn turn_lcd_off(&mut self) {
+        self.lcd_on = false;
+        self.line = 0;
+        self.ly = 0;
+        self.dot = 0;
+        self.stat_line = false;
+        self.window_line = 0;
+        self.window_drawn = false;
+        self.set_mode(Mode::HBlank);
+
+        let blank = self.get_shade_color(0, &self.dmg_palettes.bg);
+        self.back_frame.clear(blank);
+        self.publish_frame();
+    }
+
+    fn turn_lcd_on(&mut self) {
+        self.lcd_on = true;
+        self.skip_frame = true;
+        self.set_mode(Mode::ScanOam);
+        self.call_scanline_hook();
+    }
+
     fn present_image(&mut self) {
-        self.display.present();
+        // The first frame after the LCD is switched on is never shown.
+        if self.skip_frame {
+            self.skip_frame = false;
+            return;
+        }
+
+        self.publish_frame();
+    }
+
+    fn publish_frame(&mut self) {
+        match self.sgb.as_ref() {
+            Some(sgb) => sgb.compose(&self.back_frame, &mut self.front_frame),
+            None => std::mem::swap(&mut self.back_frame, &mut self.front_frame),
+        }
+        self.frame_blender.apply(&mut self.front_frame);
+        self.frame_ready = true;
+    }
+
+    /// Hands out the frame completed at the last VBlank, once.
+    pub fn take_frame(&mut self) -> Option<&FrameBuffer> {
+        if !self.frame_ready {
+            return None;
+        }
+
+        self.frame_ready = false;
+        Some(&self.front_frame)
+    }
+
+    /// The last completed frame.
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.front_frame
     }
 
     fn render_line(&mut self) {
-        if !self.lcdc.get_lcd_enable() {
+        if self.skip_frame {
             return;
         }
 
         self.select_oam_objects();
 
-        for x in 0u8..160 {
+        for x in 0..SCREEN_WIDTH as u8 {
             self.render_pixel(x, self.ly);
         }
     }
@@ -214,9 +601,14 @@ impl<'a> Gpu<'a> {
         }
 
         let mut num_obj = 0;
+        let limit = if self.debug_options.object_limit {
+            OBJECTS_PER_LINE
+        } else {
+            OAM_OBJECTS
+        };
 
         for address in (OAM_START_ADDRESS..OAM_END_ADDRESS).step_by(4) {
-            if num_obj > 9 {
+            if num_obj == limit {
                 break;
             }
 
@@ -232,140 +624,439 @@ impl<'a> Gpu<'a> {
     }
 
     fn render_pixel(&mut self, x: u8, y: u8) {
-        let mut color = self.get_bg_color(x, y);
-        if self.lcdc.get_obj_enable() {
-            color = self.get_obj_color(x, y, color);
+        let (bg_color, bg_attributes, bg_layer) = match self.get_window_pixel(x) {
+            Some((color, attributes)) => (color, attributes, Layer::Window),
+            None => {
+                let (color, attributes) = self.get_bg_pixel(x, y);
+                (color, attributes, Layer::Background)
+            }
+        };
+
+        let obj_pixel = if self.lcdc.get_obj_enable() && self.debug_options.objects {
+            self.get_obj_pixel(x, y)
+        } else {
+            None
+        };
+
+        let (color, layer) = match obj_pixel {
+            Some((obj_color, obj_attributes))
+                if self.obj_above_bg(bg_color, bg_attributes, obj_attributes) =>
+            {
+                (self.get_obj_color(obj_color, obj_attributes), Layer::Object)
+            }
+            _ => (self.get_bg_color(bg_color, bg_attributes), bg_layer),
+        };
+
+        let color = if self.debug_options.layer_tint && self.sgb.is_none() {
+            debug::tint(color, layer)
+        } else {
+            color
+        };
+
+        self.back_frame.set_pixel(x as usize, y as usize, color);
+    }
+
+    /// Returns the color index and the CGB attributes of the window pixel
+    /// at the given column of the current line, if the window covers it.
+    fn get_window_pixel(&mut self, x: u8) -> Option<(u8, u8)> {
+        // On DMG, LCDC bit 0 turns the window off as well
+        if !self.lcdc.get_window_enable()
+            || !self.debug_options.window
+            || (!self.cgb && !self.lcdc.get_priority())
+            || self.ly < self.wy
+            || (x as u16 + WINDOW_X_OFFSET as u16) < self.wx as u16
+        {
+            return None;
+        }
+
+        self.window_drawn = true;
+
+        let map_address = match self.lcdc.get_window_area() {
+            true => TILE_MAP_BLOCK_1_ADDRESS,
+            false => TILE_MAP_BLOCK_0_ADDRESS,
+        };
+        let window_x = x + WINDOW_X_OFFSET - self.wx;
+
+        Some(self.get_map_pixel(map_address, window_x, self.window_line))
+    }
+
+    fn obj_above_bg(&self, bg_color: u8, bg_attributes: u8, obj_attributes: u8) -> bool {
+        if bg_color == 0 {
+            return true;
         }
 
-        self.display.render_pixel(x, y, color);
+        // On CGB, LCDC bit 0 takes away the priority of the background entirely
+        if self.cgb && !self.lcdc.get_priority() {
+            return true;
+        }
+
+        bg_attributes & ATTR_PRIORITY_BITMASK == 0 && obj_attributes & ATTR_PRIORITY_BITMASK == 0
     }
 
-    fn get_obj_color(&mut self, x: u8, y: u8, color: DmgColor) -> DmgColor {
+    /// Returns the color index and the attributes of the object pixel at
+    /// the given position, if any object covers it.
+    fn get_obj_pixel(&self, x: u8, y: u8) -> Option<(u8, u8)> {
+        let obj_height = if self.lcdc.get_obj_size() { 16 } else { 8 };
+        let mut pixel = None;
+        let mut pixel_x_position = 0;
+
         for address in self.selected_oam_objects {
             if address == 0x0 {
                 break;
             }
 
-            let y_position = self.oam.fetch8(address).unwrap() as i32 - 16;
-            let x_position = self.oam.fetch8(address + 1).unwrap() as i32 - 8;
-            let mut tile_index = self.oam.fetch8(address + 2).unwrap();
-            // let attrs = self.oam.fetch8(address + 3).unwrap();
+            let offset = (address - OAM_START_ADDRESS) as usize;
+            let y_position = self.oam.buffer[offset] as i32 - 16;
+            let x_position = self.oam.buffer[offset + 1] as i32 - 8;
+            let mut tile_index = self.oam.buffer[offset + 2];
+            let attributes = self.oam.buffer[offset + 3];
 
-            if x as i32 >= x_position && (x as i32) < x_position + 8 {
-                if self.lcdc.get_obj_size() && y as i32 > y_position + 8 {
-                    tile_index += 1;
-                }
+            if (x as i32) < x_position || (x as i32) >= x_position + 8 {
+                continue;
+            }
+
+            // CGB gives priority to the first object in OAM, DMG to the
+            // object with the lowest X coordinate.
+            if pixel.is_some() && (self.cgb || x_position >= pixel_x_position) {
+                continue;
+            }
 
-                let line_x = x as i32 - x_position;
-                let line_y = y as i32 - y_position;
-                let tile_address =
-                    TILE_DATA_BLOCK_0_ADDRESS + (tile_index as u16 * TILE_LEN as u16);
-                let line_address = tile_address + (line_y as u16 * 2);
-                let tile_line = self.vram.fetch16(line_address).unwrap();
+            let mut line_x = (x as i32 - x_position) as u8;
+            let mut line_y = (y as i32 - y_position) as u8;
+            if attributes & ATTR_X_FLIP_BITMASK != 0 {
+                line_x = 7 - line_x;
+            }
+            if attributes & ATTR_Y_FLIP_BITMASK != 0 {
+                line_y = obj_height - 1 - line_y;
+            }
+            if obj_height == 16 {
+                tile_index &= 0xFE;
+            }
+
+            let bank = (self.cgb && attributes & ATTR_VRAM_BANK_BITMASK != 0) as u8;
+            let tile_address = TILE_DATA_BLOCK_0_ADDRESS + (tile_index as u16 * TILE_LEN as u16);
+            let color = self.get_tile_pixel(bank, tile_address, line_x, line_y);
 
-                return self.get_line_pixel_color(tile_line, line_x as u8);
+            if color != 0 {
+                pixel = Some((color, attributes));
+                pixel_x_position = x_position;
             }
         }
 
-        color
+        pixel
     }
 
-    fn get_bg_color(&mut self, x: u8, y: u8) -> DmgColor {
-        let tile_index = self.get_bg_tile_index(x, y);
-        let pixel_x = (x + self.scx) % 8;
-        let pixel_y = (y + self.scy) % 8;
+    fn get_bg_pixel(&self, x: u8, y: u8) -> (u8, u8) {
+        // On DMG, LCDC bit 0 turns the background off
+        if (!self.cgb && !self.lcdc.get_priority()) || !self.debug_options.background {
+            return (0, 0);
+        }
 
-        let line_pixels = self.get_bg_tile_line(tile_index, pixel_y);
+        let bg_x = x.wrapping_add(self.scx);
+        let bg_y = y.wrapping_add(self.scy);
+        let map_address = match self.lcdc.get_bg_area() {
+            true => TILE_MAP_BLOCK_1_ADDRESS,
+            false => TILE_MAP_BLOCK_0_ADDRESS,
+        };
 
-        self.get_line_pixel_color(line_pixels, pixel_x)
+        self.get_map_pixel(map_address, bg_x, bg_y)
     }
 
-    fn get_bg_tile_line(&mut self, tile_index: u8, line: u8) -> u16 {
+    /// Returns the color index and the CGB attributes of a pixel in one of
+    /// the 256x256 tile maps.
+    fn get_map_pixel(&self, map_address: u16, x: u8, y: u8) -> (u8, u8) {
+        let tile_no = (y as u16 / 8 * 32) + (x as u16 / 8);
+        let tile_index = self.read_vram(0, map_address + tile_no);
+        let attributes = if self.cgb {
+            self.read_vram(1, map_address + tile_no)
+        } else {
+            0
+        };
+
+        let mut pixel_x = x % 8;
+        let mut pixel_y = y % 8;
+        if attributes & ATTR_X_FLIP_BITMASK != 0 {
+            pixel_x = 7 - pixel_x;
+        }
+        if attributes & ATTR_Y_FLIP_BITMASK != 0 {
+            pixel_y = 7 - pixel_y;
+        }
+
+        let bank = (attributes & ATTR_VRAM_BANK_BITMASK != 0) as u8;
+        let tile_address = self.get_bg_tile_address(tile_index);
+
+        (
+            self.get_tile_pixel(bank, tile_address, pixel_x, pixel_y),
+            attributes,
+        )
+    }
+
+    fn get_bg_tile_address(&self, tile_index: u8) -> u16 {
         let addressing_mode = self.lcdc.get_bg_characters();
-        let tile_address = match addressing_mode {
+        match addressing_mode {
             true => TILE_DATA_BLOCK_0_ADDRESS + (tile_index as u16 * TILE_LEN as u16),
             false => {
                 if tile_index <= 127 {
                     TILE_DATA_BLOCK_2_ADDRESS + (tile_index as u16 * TILE_LEN as u16)
                 } else {
-                    TILE_DATA_BLOCK_1_ADDRESS + (tile_index as u16 * TILE_LEN as u16)
+                    TILE_DATA_BLOCK_1_ADDRESS + ((tile_index - 128) as u16 * TILE_LEN as u16)
                 }
             }
-        };
+        }
+    }
 
-        /*if tile_index == 1 {
-            println!("{:#X}", tile_address);
-            for i in 0..8 {
-                println!(
-                    "{}: {:#X}",
-                    i,
-                    self.vram.fetch16(tile_address + (i * 2)).unwrap()
-                )
-            }
-        }*/
+    fn get_tile_pixel(&self, bank: u8, tile_address: u16, x: u8, y: u8) -> u8 {
+        let line_address = tile_address + (y as u16 * 2);
+        let lsb = self.read_vram(bank, line_address);
+        let msb = self.read_vram(bank, line_address + 1);
 
-        let line_address = tile_address + (line as u16 * 2);
-        self.vram.fetch16(line_address).unwrap()
+        let bit = 7 - x;
+        ((lsb >> bit) & 1) | (((msb >> bit) & 1) << 1)
     }
 
-    fn get_bg_tile_index(&mut self, x: u8, y: u8) -> u8 {
-        let bg_x = x + self.scx;
-        let bg_y = y + self.scy;
+    fn read_vram(&self, bank: u8, address: u16) -> u8 {
+        let vram = if bank == 0 {
+            &self.vram
+        } else {
+            &self.vram_bank1
+        };
 
-        let tile_x = bg_x / 8;
-        let tile_y = bg_y / 8;
+        vram.buffer[(address - VRAM_START_ADDRESS) as usize]
+    }
 
-        let tile_no = (tile_y as u16 * 32) + (tile_x as u16);
+    fn get_bg_color(&self, color: u8, attributes: u8) -> DmgColor {
+        if self.cgb {
+            let rgb555 = self
+                .bg_palette
+                .get_rgb555(attributes & ATTR_PALETTE_BITMASK, color);
+            return self.color_correction.apply(rgb555);
+        }
 
-        let tile_map_block = self.lcdc.get_bg_area();
-        let address = match tile_map_block {
-            true => TILE_MAP_BLOCK_1_ADDRESS + tile_no,
-            false => TILE_MAP_BLOCK_0_ADDRESS + tile_no,
-        };
+        if !self.lcdc.get_priority() {
+            return self.get_shade_color(0, &self.dmg_palettes.bg);
+        }
 
-        self.vram.fetch8(address).unwrap()
+        self.get_dmg_palette_color(self.bgp, color, &self.dmg_palettes.bg)
     }
 
-    fn get_line_pixel_color(&self, line: u16, line_x: u8) -> DmgColor {
-        let lsb = (line >> (15 - line_x as u16)) & 1;
-        let msb = (line >> (7 - line_x as u16)) & 1;
+    fn get_obj_color(&self, color: u8, attributes: u8) -> DmgColor {
+        if self.cgb {
+            let rgb555 = self
+                .obj_palette
+                .get_rgb555(attributes & ATTR_PALETTE_BITMASK, color);
+            return self.color_correction.apply(rgb555);
+        }
 
-        let color = lsb | (msb << 1);
+        if attributes & ATTR_DMG_PALETTE_BITMASK != 0 {
+            self.get_dmg_palette_color(self.obp1, color, &self.dmg_palettes.obj1)
+        } else {
+            self.get_dmg_palette_color(self.obp0, color, &self.dmg_palettes.obj0)
+        }
+    }
+
+    fn get_dmg_palette_color(&self, palette: u8, color: u8, colors: &Palette) -> DmgColor {
+        let shade = (palette >> (color * 2)) & 0b11;
 
-        let palette_color = (self.bgp >> (color * 2)) & 0b11;
+        self.get_shade_color(shade, colors)
+    }
 
-        match palette_color {
-            0 => DmgColor::White,
-            1 => DmgColor::LightGrey,
-            2 => DmgColor::DarkGrey,
-            3 => DmgColor::Black,
-            _ => panic!("Unsupported color value"),
+    /// The SGB needs plain shades to colorize them.
+    fn get_shade_color(&self, shade: u8, colors: &Palette) -> DmgColor {
+        if self.sgb.is_some() {
+            return match shade {
+                0 => DmgColor::White,
+                1 => DmgColor::LightGrey,
+                2 => DmgColor::DarkGrey,
+                3 => DmgColor::Black,
+                _ => panic!("Unsupported color value"),
+            };
         }
+
+        colors[shade as usize]
     }
 }
 
-impl<'a> FetchWrite for Gpu<'a> {
+impl FetchWrite for Gpu {
     fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        if self.cpu_access_blocked(address) || self.cgb_register_unavailable(address) {
+            return Ok(0xFF);
+        }
+
         let target = self.get_address_target(address)?;
 
         target.fetch8(address)
     }
 
     fn fetch16(&mut self, address: u16) -> Result<u16, std::io::Error> {
+        if self.cpu_access_blocked(address) {
+            return Ok(0xFFFF);
+        }
+
         let target = self.get_address_target(address)?;
 
         target.fetch16(address)
     }
 
     fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        // LY is read only
+        if address == LY_ADDRESS
+            || self.cpu_access_blocked(address)
+            || self.cgb_register_unavailable(address)
+        {
+            return Ok(());
+        }
+
         let target = self.get_address_target(address)?;
 
         target.write8(address, value)
     }
 
     fn write16(&mut self, address: u16, value: u16) -> std::io::Result<()> {
+        if self.cpu_access_blocked(address) {
+            return Ok(());
+        }
+
         let target = self.get_address_target(address)?;
 
         target.write16(address, value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_DOTS: u32 = SCANLINE_CYCLES * (VBLANK_END_LINE as u32 + 1);
+
+    const STAT_HBLANK_INTERRUPT: u8 = 1 << 3;
+    const STAT_OAM_INTERRUPT: u8 = 1 << 5;
+    const STAT_LYC_INTERRUPT: u8 = 1 << 6;
+
+    /// A GPU with the LCD and the background on.
+    fn gpu_on() -> (Gpu, Interrupts) {
+        let mut gpu = Gpu::new();
+        gpu.write8(LCDC_ADDRESS, 0x81).unwrap();
+
+        (gpu, Interrupts::new())
+    }
+
+    fn run(gpu: &mut Gpu, interrupts: &mut Interrupts, dots: u32) {
+        for _ in 0..dots {
+            gpu.next(1, interrupts);
+        }
+    }
+
+    /// Runs dot by dot, returns LY and dot of each STAT interrupt request.
+    fn stat_requests(gpu: &mut Gpu, interrupts: &mut Interrupts, dots: u32) -> Vec<(u8, u32)> {
+        let mut requests = Vec::new();
+
+        for _ in 0..dots {
+            gpu.next(1, interrupts);
+            if interrupts.lcd_stat_request() {
+                interrupts.set_lcd_stat_request(false);
+                requests.push((gpu.ly, gpu.dot));
+            }
+        }
+
+        requests
+    }
+
+    #[test]
+    fn cpu_access_blocked_by_mode() {
+        let mut gpu = Gpu::new();
+        let mut interrupts = Interrupts::new();
+        gpu.write8(VRAM_START_ADDRESS, 0x12).unwrap();
+        gpu.write8(OAM_START_ADDRESS, 0x34).unwrap();
+        gpu.write8(LCDC_ADDRESS, 0x80).unwrap();
+
+        // OAM scan
+        run(&mut gpu, &mut interrupts, 1);
+        assert_eq!(gpu.fetch8(VRAM_START_ADDRESS).unwrap(), 0x12);
+        assert_eq!(gpu.fetch8(OAM_START_ADDRESS).unwrap(), 0xFF);
+        gpu.write8(OAM_START_ADDRESS, 0x56).unwrap();
+
+        // Drawing
+        run(&mut gpu, &mut interrupts, OAM_CYCLES);
+        assert_eq!(gpu.stat.get_mode(), Mode::ScanVram);
+        assert_eq!(gpu.fetch8(VRAM_START_ADDRESS).unwrap(), 0xFF);
+        assert_eq!(gpu.fetch8(OAM_START_ADDRESS).unwrap(), 0xFF);
+        gpu.write8(VRAM_START_ADDRESS, 0x56).unwrap();
+
+        // HBlank, the writes above were dropped
+        run(&mut gpu, &mut interrupts, VRAM_CYCLES);
+        assert_eq!(gpu.stat.get_mode(), Mode::HBlank);
+        assert_eq!(gpu.fetch8(VRAM_START_ADDRESS).unwrap(), 0x12);
+        assert_eq!(gpu.fetch8(OAM_START_ADDRESS).unwrap(), 0x34);
+
+        gpu.write8(OAM_START_ADDRESS, 0x56).unwrap();
+        assert_eq!(gpu.fetch8(OAM_START_ADDRESS).unwrap(), 0x56);
+    }
+
+    #[test]
+    fn stat_interrupt_on_rising_edge_only() {
+        let (mut gpu, mut interrupts) = gpu_on();
+        gpu.write8(STAT_ADDRESS, STAT_HBLANK_INTERRUPT | STAT_OAM_INTERRUPT)
+            .unwrap();
+
+        let requests = stat_requests(&mut gpu, &mut interrupts, 2 * SCANLINE_CYCLES);
+
+        // From HBlank straight into OAM scan the line stays high, so the
+        // start of line 1 requests nothing.
+        let hblank_dot = OAM_CYCLES + VRAM_CYCLES;
+        assert_eq!(requests, vec![(0, 1), (0, hblank_dot), (1, hblank_dot)]);
+    }
+
+    #[test]
+    fn ly_reads_zero_early_in_line_153() {
+        let (mut gpu, mut interrupts) = gpu_on();
+        gpu.write8(STAT_ADDRESS, STAT_LYC_INTERRUPT).unwrap();
+        gpu.write8(LYC_ADDRESS, 0).unwrap();
+        run(&mut gpu, &mut interrupts, 1);
+        interrupts.set_lcd_stat_request(false);
+
+        run(
+            &mut gpu,
+            &mut interrupts,
+            VBLANK_END_LINE as u32 * SCANLINE_CYCLES + LINE_153_LY_RESET_CYCLES - 2,
+        );
+        assert_eq!(gpu.fetch8(LY_ADDRESS).unwrap(), VBLANK_END_LINE);
+        assert!(!interrupts.lcd_stat_request());
+
+        run(&mut gpu, &mut interrupts, 1);
+        assert_eq!(gpu.fetch8(LY_ADDRESS).unwrap(), 0);
+        assert!(interrupts.lcd_stat_request());
+
+        // The match lasts into line 0, no second request
+        interrupts.set_lcd_stat_request(false);
+        run(&mut gpu, &mut interrupts, SCANLINE_CYCLES);
+        assert_eq!(gpu.fetch8(LY_ADDRESS).unwrap(), 0);
+        assert!(!interrupts.lcd_stat_request());
+    }
+
+    #[test]
+    fn lcd_off_shows_blank_frame_and_skips_first_frame_after_on() {
+        let (mut gpu, mut interrupts) = gpu_on();
+        // Every background pixel in the darkest shade
+        gpu.write8(BGP_ADDRESS, 0xFF).unwrap();
+        let white = gpu.dmg_palettes.bg[0];
+        let black = gpu.dmg_palettes.bg[3];
+
+        run(&mut gpu, &mut interrupts, FRAME_DOTS);
+        assert!(gpu.take_frame().is_none());
+
+        run(&mut gpu, &mut interrupts, FRAME_DOTS);
+        assert_eq!(gpu.take_frame().unwrap().get_pixel(0, 0), black);
+
+        gpu.write8(LCDC_ADDRESS, 0x01).unwrap();
+        run(&mut gpu, &mut interrupts, 1);
+        assert_eq!(gpu.take_frame().unwrap().get_pixel(0, 0), white);
+        assert_eq!(gpu.fetch8(LY_ADDRESS).unwrap(), 0);
+        assert_eq!(gpu.stat.get_mode(), Mode::HBlank);
+
+        gpu.write8(LCDC_ADDRESS, 0x81).unwrap();
+        run(&mut gpu, &mut interrupts, FRAME_DOTS);
+        assert!(gpu.take_frame().is_none());
+        run(&mut gpu, &mut interrupts, FRAME_DOTS);
+        assert_eq!(gpu.take_frame().unwrap().get_pixel(0, 0), black);
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+#![allow(clippy::upper_case_acronyms)]
 
 use crate::{bus::FetchWrite, register::Register8};
 
@@ -73,12 +74,15 @@ impl FetchWrite for LCDC {
 }
 
 const MODE_BITMASK: u8 = 0b11;
+const READ_ONLY_BITMASK: u8 = 0b111;
+const UNUSED_BITMASK: u8 = 1 << 7;
 const LYC_MATCH_BITMASK: u8 = 1 << 2;
 const MODE_HBLANK_INTERRUPT_BITMASK: u8 = 1 << 3;
 const MODE_VBLANK_INTERRUPT_BITMASK: u8 = 1 << 4;
 const MODE_OAM_INTERRUPT_BITMASK: u8 = 1 << 5;
 const LYC_MATCH_INTERRUPT_BITMASK: u8 = 1 << 6;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Mode {
     HBlank = 0,
     VBlank = 1,
@@ -152,7 +156,7 @@ impl STAT {
 
 impl FetchWrite for STAT {
     fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
-        self.value.fetch8(address)
+        Ok(self.value | UNUSED_BITMASK)
     }
 
     fn fetch16(&mut self, address: u16) -> Result<u16, std::io::Error> {
@@ -160,7 +164,47 @@ impl FetchWrite for STAT {
     }
 
     fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
-        self.value.write8(address, value)
+        // Mode and LYC match flag can't be written by the CPU
+        self.value =
+            (value & !(READ_ONLY_BITMASK | UNUSED_BITMASK)) | (self.value & READ_ONLY_BITMASK);
+
+        Ok(())
+    }
+
+    fn write16(&mut self, address: u16, value: u16) -> std::io::Result<()> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+}
+
+const VRAM_BANK_BITMASK: u8 = 1;
+
+pub struct VBK {
+    value: Register8,
+}
+
+impl VBK {
+    pub fn new() -> Self {
+        VBK { value: 0 }
+    }
+
+    pub fn get_bank(&self) -> u8 {
+        self.value & VRAM_BANK_BITMASK
+    }
+}
+
+impl FetchWrite for VBK {
+    fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        Ok(self.value | !VRAM_BANK_BITMASK)
+    }
+
+    fn fetch16(&mut self, address: u16) -> Result<u16, std::io::Error> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+
+    fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        self.value = value & VRAM_BANK_BITMASK;
+
+        Ok(())
     }
 
     fn write16(&mut self, address: u16, value: u16) -> std::io::Result<()> {
@@ -0,0 +1,149 @@
+use std::ops::RangeInclusive;
+
+use crate::gpu::Mode;
+
+/// PPU position and time at which a hook fired.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HookContext {
+    pub ly: u8,
+    pub dot: u32,
+    /// Clock cycles since power on, at normal speed
+    pub cycles: u64,
+}
+
+pub type ScanlineHook = Box<dyn FnMut(&HookContext)>;
+pub type ModeHook = Box<dyn FnMut(Mode, &HookContext)>;
+pub type VBlankHook = Box<dyn FnMut(&HookContext)>;
+/// Called with the address and the value written.
+pub type WriteHook = Box<dyn FnMut(u16, u8, &HookContext)>;
+
+/// Callbacks fired by the PPU.
+#[derive(Default)]
+pub struct GpuHooks {
+    pub scanline: Option<ScanlineHook>,
+    pub mode: Option<ModeHook>,
+    pub vblank: Option<VBlankHook>,
+}
+
+/// Callbacks fired on writes to an address range.
+#[derive(Default)]
+pub struct WriteHooks {
+    hooks: Vec<(RangeInclusive<u16>, WriteHook)>,
+}
+
+impl WriteHooks {
+    pub fn add(&mut self, range: RangeInclusive<u16>, hook: WriteHook) {
+        self.hooks.push((range, hook));
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn call(&mut self, address: u16, value: u8, context: &HookContext) {
+        for (range, hook) in self.hooks.iter_mut() {
+            if range.contains(&address) {
+                hook(address, value, context);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        bus::{Bus, FetchWrite},
+        cartridge::Cartridge,
+        gpu::Gpu,
+        interrupts::Interrupts,
+    };
+
+    const LCDC_ADDRESS: u16 = 0xFF40;
+    const FRAME_DOTS: u32 = 456 * 154;
+
+    fn context(ly: u8, dot: u32, cycles: u64) -> HookContext {
+        HookContext { ly, dot, cycles }
+    }
+
+    #[test]
+    fn gpu_hooks_fire_with_ppu_position() {
+        let scanlines = Rc::new(RefCell::new(Vec::new()));
+        let modes = Rc::new(RefCell::new(Vec::new()));
+        let vblanks = Rc::new(RefCell::new(Vec::new()));
+
+        let mut gpu = Gpu::new();
+        let hooks = gpu.hooks_mut();
+        let log = scanlines.clone();
+        hooks.scanline = Some(Box::new(move |context| log.borrow_mut().push(*context)));
+        let log = modes.clone();
+        hooks.mode = Some(Box::new(move |mode, context| {
+            log.borrow_mut().push((mode, *context))
+        }));
+        let log = vblanks.clone();
+        hooks.vblank = Some(Box::new(move |context| log.borrow_mut().push(*context)));
+
+        let mut interrupts = Interrupts::new();
+        gpu.write8(LCDC_ADDRESS, 0x81).unwrap();
+        for _ in 0..FRAME_DOTS {
+            gpu.next(1, &mut interrupts);
+        }
+
+        let scanlines = scanlines.borrow();
+        assert_eq!(scanlines.len(), 155);
+        assert_eq!(scanlines[0], context(0, 0, 0));
+        assert_eq!(scanlines[1], context(1, 0, 456));
+        assert_eq!(scanlines[153], context(153, 0, 153 * 456));
+        assert_eq!(scanlines[154], context(0, 0, FRAME_DOTS as u64));
+
+        let modes = modes.borrow();
+        assert_eq!(
+            modes[..4],
+            [
+                (Mode::ScanOam, context(0, 0, 0)),
+                (Mode::ScanVram, context(0, 80, 80)),
+                (Mode::HBlank, context(0, 252, 252)),
+                (Mode::ScanOam, context(1, 0, 456)),
+            ]
+        );
+        assert!(modes.contains(&(Mode::VBlank, context(144, 0, 144 * 456))));
+
+        assert_eq!(*vblanks.borrow(), [context(144, 0, 144 * 456)]);
+    }
+
+    #[test]
+    fn write_hooks_fire_in_their_range() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+
+        let mut bus = Bus::new(Cartridge::from_rom(vec![0; 0x8000]), Gpu::new(), None);
+        let log = writes.clone();
+        bus.write_hooks_mut().add(
+            0xFF10..=0xFF26,
+            Box::new(move |address, value, _| log.borrow_mut().push((address, value))),
+        );
+        let log = writes.clone();
+        bus.write_hooks_mut().add(
+            0xFF26..=0xFF26,
+            Box::new(move |address, value, _| log.borrow_mut().push((address, !value))),
+        );
+
+        for address in [0xFF0F, 0xFF10, 0xFF26, 0xFF27, 0xC000] {
+            bus.write8(address, 0x80).unwrap();
+        }
+
+        assert_eq!(
+            *writes.borrow(),
+            [(0xFF10, 0x80), (0xFF26, 0x80), (0xFF26, 0x7F)]
+        );
+
+        bus.write_hooks_mut().clear();
+        bus.write8(0xFF10, 0x80).unwrap();
+        assert_eq!(writes.borrow().len(), 3);
+    }
+}
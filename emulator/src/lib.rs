@@ -5,9 +5,20 @@ pub mod cartridge;
 pub mod constants;
 pub mod cpu;
 mod disassembler;
+mod dma;
+pub mod gameboy;
+pub mod gbs;
 pub mod gpu;
+pub mod hooks;
 mod interrupts;
+pub mod link;
+pub mod palette;
+pub mod printer;
 mod ram;
 pub mod register;
-mod spu;
+pub mod serial;
+mod sgb;
+mod speed;
+pub mod spu;
 mod timer;
+mod wram;
@@ -0,0 +1,190 @@
+use std::{cell::RefCell, collections::VecDeque, io, rc::Rc};
+
+use crate::{bus::Bus, constants::FRAME_CYCLES, cpu::Cpu, gpu::FrameBuffer, serial::SerialLink};
+
+#[derive(Default)]
+struct CableSide {
+    /// SB when this side started waiting for the peer's clock, and the
+    /// bits the peer clocked out of it since
+    ready: Option<(u8, u8)>,
+    /// Clock pulses from the peer with its bits
+    pulses: VecDeque<bool>,
+}
+
+/// One end of a link cable between two Game Boys in the same process.
+pub struct CableEnd {
+    sides: Rc<RefCell<[CableSide; 2]>>,
+    side: usize,
+}
+
+/// Creates both ends of a link cable.
+pub fn link_cable() -> (CableEnd, CableEnd) {
+    let sides = Rc::new(RefCell::new([CableSide::default(), CableSide::default()]));
+
+    (
+        CableEnd {
+            sides: sides.clone(),
+            side: 0,
+        },
+        CableEnd { sides, side: 1 },
+    )
+}
+
+impl SerialLink for CableEnd {
+    fn exchange(&mut self, bit: bool) -> bool {
+        let mut sides = self.sides.borrow_mut();
+        let peer = &mut sides[1 - self.side];
+        peer.pulses.push_back(bit);
+
+        match peer.ready.as_mut() {
+            Some((sb, shifted)) if *shifted < 8 => {
+                let peer_bit = *sb & (0x80 >> *shifted) != 0;
+                *shifted += 1;
+                peer_bit
+            }
+            _ => true,
+        }
+    }
+
+    fn poll(&mut self, sb: Option<u8>) -> Option<bool> {
+        let mut sides = self.sides.borrow_mut();
+        let side = &mut sides[self.side];
+
+        match sb {
+            Some(sb) => {
+                side.ready.get_or_insert((sb, 0));
+                side.pulses.pop_front()
+            }
+            None => {
+                // Pulses nobody waits for are lost
+                side.ready = None;
+                side.pulses.clear();
+                None
+            }
+        }
+    }
+}
+
+/// Two Game Boys connected by a link cable, run in cycle lockstep: the one
+/// that is behind always executes the next instruction. Cycles are counted
+/// at the base clock, so either one may run in double speed.
+pub struct LinkedGameBoys {
+    cpus: [Cpu; 2],
+    buses: [Bus; 2],
+    cycles: [i64; 2],
+}
+
+impl LinkedGameBoys {
+    pub fn new(
+        (first_cpu, mut first_bus): (Cpu, Bus),
+        (second_cpu, mut second_bus): (Cpu, Bus),
+    ) -> Self {
+        let (first_end, second_end) = link_cable();
+        first_bus.set_serial_link(Some(Box::new(first_end)));
+        second_bus.set_serial_link(Some(Box::new(second_end)));
+
+        LinkedGameBoys {
+            cpus: [first_cpu, second_cpu],
+            buses: [first_bus, second_bus],
+            cycles: [0, 0],
+        }
+    }
+
+    pub fn bus(&self, index: usize) -> &Bus {
+        &self.buses[index]
+    }
+
+    pub fn bus_mut(&mut self, index: usize) -> &mut Bus {
+        &mut self.buses[index]
+    }
+
+    pub fn frame_buffer(&self, index: usize) -> &FrameBuffer {
+        self.buses[index].gpu.frame_buffer()
+    }
+
+    /// Executes one instruction on the Game Boy that is behind, returns
+    /// how far both have run together since the last step.
+    pub fn step(&mut self) -> io::Result<i64> {
+        let before = self.cycles[0].min(self.cycles[1]);
+
+        let index = if self.cycles[0] <= self.cycles[1] {
+            0
+        } else {
+            1
+        };
+        let cycles = self.cpus[index].next(&mut self.buses[index])?;
+        self.cycles[index] += self.buses[index].base_clock_cycles(cycles) as i64;
+
+        Ok(self.cycles[0].min(self.cycles[1]) - before)
+    }
+
+    /// Runs both Game Boys for at least the given clock cycles.
+    pub fn run(&mut self, cycles: i64) -> io::Result<()> {
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            elapsed += self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs both Game Boys for the duration of one frame.
+    pub fn run_frame(&mut self) -> io::Result<()> {
+        self.run(FRAME_CYCLES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bus::FetchWrite, cartridge::Cartridge, gpu::Gpu};
+
+    const SB_ADDRESS: u16 = 0xFF01;
+    const INTERRUPT_REQUEST_ADDRESS: u16 = 0xFF0F;
+    const SERIAL_BITMASK: u8 = 1 << 3;
+
+    /// Loads `sb` into SB, starts a transfer with `sc` and loops.
+    fn transfer_rom(sb: u8, sc: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x3E, sb, // LD A, sb
+            0xE0, 0x01, // LDH (SB), A
+            0x3E, sc, // LD A, sc
+            0xE0, 0x02, // LDH (SC), A
+            0x18, 0xFE, // JR -2
+        ];
+        rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    fn game_boy(rom: Vec<u8>) -> (Cpu, Bus) {
+        (
+            Cpu::new(true, false, false),
+            Bus::new(Cartridge::from_rom(rom), Gpu::new(), None),
+        )
+    }
+
+    #[test]
+    fn trades_a_byte() {
+        let mut game_boys = LinkedGameBoys::new(
+            game_boy(transfer_rom(0x42, 0x81)),
+            game_boy(transfer_rom(0x99, 0x80)),
+        );
+
+        game_boys.run(0x4000).unwrap();
+
+        let master = game_boys.bus_mut(0);
+        assert_eq!(master.fetch8(SB_ADDRESS).unwrap(), 0x99);
+        assert_ne!(
+            master.fetch8(INTERRUPT_REQUEST_ADDRESS).unwrap() & SERIAL_BITMASK,
+            0
+        );
+
+        let slave = game_boys.bus_mut(1);
+        assert_eq!(slave.fetch8(SB_ADDRESS).unwrap(), 0x42);
+        assert_ne!(
+            slave.fetch8(INTERRUPT_REQUEST_ADDRESS).unwrap() & SERIAL_BITMASK,
+            0
+        );
+    }
+}
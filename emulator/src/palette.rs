@@ -0,0 +1,314 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    str::FromStr,
+};
+
+use crate::{cartridge::Cartridge, gpu::DmgColor};
+
+/// Four colors, from color 0 (lightest shade) to color 3 (darkest shade).
+pub type Palette = [DmgColor; 4];
+
+const fn palette(colors: [u32; 4]) -> Palette {
+    let mut palette = [DmgColor::White; 4];
+    let mut index = 0;
+    while index < 4 {
+        let color = colors[index];
+        palette[index] = DmgColor::Rgb((color >> 16) as u8, (color >> 8) as u8, color as u8);
+        index += 1;
+    }
+    palette
+}
+
+pub const GREY: Palette = palette([0xFFFFFF, 0xABABAB, 0x555555, 0x000000]);
+pub const DMG_GREEN: Palette = palette([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]);
+pub const POCKET: Palette = palette([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]);
+pub const LIGHT: Palette = palette([0x00B581, 0x009A71, 0x00694A, 0x004F3B]);
+
+const CGB_BROWN: Palette = palette([0xFFFFFF, 0xFFAD63, 0x843100, 0x000000]);
+const CGB_RED: Palette = palette([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]);
+const CGB_DARK_BROWN: Palette = palette([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]);
+const CGB_BLUE: Palette = palette([0xFFFFFF, 0x65A49B, 0x0000FE, 0x000000]);
+const CGB_DARK_BLUE: Palette = palette([0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000]);
+const CGB_GREY: Palette = palette([0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000]);
+const CGB_PASTEL: Palette = palette([0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000]);
+const CGB_RED_YELLOW: Palette = palette([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000]);
+const CGB_YELLOW: Palette = palette([0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000]);
+const CGB_LIGHT_BLUE: Palette = palette([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]);
+const CGB_ORANGE: Palette = palette([0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000]);
+const CGB_GREEN: Palette = palette([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]);
+const CGB_DARK_GREEN: Palette = palette([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000]);
+const CGB_INVERTED: Palette = palette([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF]);
+
+/// Palettes for the background and both object palettes of DMG output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PaletteSet {
+    pub bg: Palette,
+    pub obj0: Palette,
+    pub obj1: Palette,
+}
+
+impl Default for PaletteSet {
+    fn default() -> Self {
+        PaletteSet::uniform(GREY)
+    }
+}
+
+/// The palettes the CGB boot ROM offers for DMG games, named after the
+/// button combination selecting them.
+const CGB_PRESETS: [(&str, PaletteSet); 12] = [
+    ("up", PaletteSet::uniform(CGB_BROWN)),
+    ("up-a", PaletteSet::uniform(CGB_RED)),
+    ("up-b", PaletteSet::uniform(CGB_DARK_BROWN)),
+    ("left", PaletteSet::new(CGB_BLUE, CGB_RED, CGB_DARK_GREEN)),
+    ("left-a", PaletteSet::new(CGB_DARK_BLUE, CGB_RED, CGB_BROWN)),
+    ("left-b", PaletteSet::uniform(CGB_GREY)),
+    ("down", PaletteSet::uniform(CGB_PASTEL)),
+    ("down-a", PaletteSet::uniform(CGB_RED_YELLOW)),
+    (
+        "down-b",
+        PaletteSet::new(CGB_YELLOW, CGB_LIGHT_BLUE, CGB_LIGHT_BLUE),
+    ),
+    ("right", PaletteSet::uniform(CGB_ORANGE)),
+    ("right-a", PaletteSet::new(CGB_GREEN, CGB_RED, CGB_RED)),
+    ("right-b", PaletteSet::uniform(CGB_INVERTED)),
+];
+
+/// Index of the palette the CGB boot ROM picks for games it does not know,
+/// `right-a`
+const CGB_DEFAULT_PRESET: usize = 10;
+
+/// Title checksums the CGB boot ROM colorizes, with the index of the preset
+/// it uses. The checksum is the sum of the 16 title bytes. Only a selection
+/// of the boot ROM's table is listed.
+const CGB_TITLE_CHECKSUMS: [(u8, usize); 4] = [
+    (0x14, 1), // POKEMON RED, up-a
+    (0xAA, 9), // POKEMON GREEN, right
+    (0x49, 6), // KIRBY DREAM LAND, down
+    (0x70, 0), // ZELDA, up
+];
+
+/// Checksums several titles share. The boot ROM tells them apart by the
+/// fourth letter of the title.
+const CGB_SHARED_TITLE_CHECKSUMS: [(u8, u8, usize); 1] = [
+    (0x61, b'E', 3), // POKEMON BLUE, left
+];
+
+/// Every checksum of the boot ROM's table that needs the fourth letter,
+/// also those of titles missing above
+const CGB_AMBIGUOUS_CHECKSUMS: [u8; 14] = [
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+
+fn title_checksum(title: &[u8]) -> u8 {
+    title.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Index of the preset the CGB boot ROM picks for a title, if it knows it.
+fn cgb_preset_index(title: &[u8]) -> Option<usize> {
+    let checksum = title_checksum(title);
+
+    if !CGB_AMBIGUOUS_CHECKSUMS.contains(&checksum) {
+        return CGB_TITLE_CHECKSUMS
+            .iter()
+            .find(|(known, _)| *known == checksum)
+            .map(|(_, index)| *index);
+    }
+
+    let fourth_letter = *title.get(3)?;
+    CGB_SHARED_TITLE_CHECKSUMS
+        .iter()
+        .find(|(known, letter, _)| *known == checksum && *letter == fourth_letter)
+        .map(|(_, _, index)| *index)
+}
+
+impl PaletteSet {
+    pub const fn new(bg: Palette, obj0: Palette, obj1: Palette) -> Self {
+        PaletteSet { bg, obj0, obj1 }
+    }
+
+    pub const fn uniform(palette: Palette) -> Self {
+        PaletteSet::new(palette, palette, palette)
+    }
+
+    /// Looks up a built-in palette: `grey`, `dmg`, `pocket`, `light` or
+    /// one of the CGB boot ROM palettes (`up`, `up-a`, ..., `right-b`).
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "grey" => Some(PaletteSet::uniform(GREY)),
+            "dmg" => Some(PaletteSet::uniform(DMG_GREEN)),
+            "pocket" => Some(PaletteSet::uniform(POCKET)),
+            "light" => Some(PaletteSet::uniform(LIGHT)),
+            _ => CGB_PRESETS
+                .iter()
+                .find(|(preset, _)| *preset == name)
+                .map(|(_, palettes)| *palettes),
+        }
+    }
+
+    /// Picks the palette the CGB boot ROM would use for a DMG game.
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        let index = if cartridge.is_licensed_by_nintendo() {
+            cgb_preset_index(cartridge.title()).unwrap_or(CGB_DEFAULT_PRESET)
+        } else {
+            CGB_DEFAULT_PRESET
+        };
+
+        CGB_PRESETS[index].1
+    }
+
+    /// Loads palettes from a text file with one `bg`, `obj0` or `obj1`
+    /// line, each followed by four hex colors from lightest to darkest:
+    ///
+    /// ```text
+    /// # comment
+    /// bg   = E0F8D0 88C070 346856 081820
+    /// obj0 = E0F8D0 88C070 346856 081820
+    /// ```
+    ///
+    /// Object palettes that are not given use the background palette.
+    pub fn load(path: &str) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+impl FromStr for PaletteSet {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+        let mut bg = None;
+        let mut obj0 = None;
+        let mut obj1 = None;
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, colors) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("Expected `name = colors`: {}", line)))?;
+
+            let colors = colors
+                .split_whitespace()
+                .map(|color| {
+                    let color = color.trim_start_matches('#');
+                    u32::from_str_radix(color, 16)
+                        .ok()
+                        .filter(|_| color.len() == 6)
+                        .ok_or_else(|| invalid(format!("Invalid color: {}", color)))
+                })
+                .collect::<io::Result<Vec<u32>>>()?;
+            let colors: [u32; 4] = colors
+                .try_into()
+                .map_err(|_| invalid(format!("Expected four colors: {}", line)))?;
+
+            match name.trim() {
+                "bg" => bg = Some(palette(colors)),
+                "obj0" => obj0 = Some(palette(colors)),
+                "obj1" => obj1 = Some(palette(colors)),
+                name => return Err(invalid(format!("Unknown palette: {}", name))),
+            }
+        }
+
+        let bg = bg.ok_or_else(|| invalid("Missing bg palette".to_string()))?;
+
+        Ok(PaletteSet::new(bg, obj0.unwrap_or(bg), obj1.unwrap_or(bg)))
+    }
+}
+
+/// Simulates how the CGB screen displays its 15 bit colors. Without
+/// correction, colors look a lot more saturated than on the real LCD.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorCorrection {
+    /// Scale channels linearly
+    #[default]
+    None,
+    /// Apply the brightness response of the LCD to each channel
+    Curves,
+    /// Darken and mix channels, like colors bleed into each other on the LCD
+    Desaturate,
+}
+
+/// Brightness of a 5 bit channel value on the CGB LCD
+const LCD_CURVE: [u8; 32] = [
+    0, 6, 12, 20, 28, 36, 45, 56, 66, 76, 88, 100, 113, 125, 137, 149, 161, 172, 182, 192, 202,
+    210, 218, 225, 232, 238, 243, 247, 250, 252, 254, 255,
+];
+
+impl ColorCorrection {
+    pub fn apply(&self, rgb555: u16) -> DmgColor {
+        let r = rgb555 & 0x1F;
+        let g = (rgb555 >> 5) & 0x1F;
+        let b = (rgb555 >> 10) & 0x1F;
+
+        match self {
+            ColorCorrection::None => DmgColor::from_rgb555(rgb555),
+            ColorCorrection::Curves => DmgColor::Rgb(
+                LCD_CURVE[r as usize],
+                LCD_CURVE[g as usize],
+                LCD_CURVE[b as usize],
+            ),
+            ColorCorrection::Desaturate => DmgColor::Rgb(
+                ((r * 13 + g * 2 + b) >> 1) as u8,
+                ((g * 3 + b) << 1) as u8,
+                ((r * 3 + g * 2 + b * 11) >> 1) as u8,
+            ),
+        }
+    }
+}
+
+impl FromStr for ColorCorrection {
+    type Err = io::Error;
+
+    fn from_str(name: &str) -> io::Result<Self> {
+        match name {
+            "none" => Ok(ColorCorrection::None),
+            "curves" => Ok(ColorCorrection::Curves),
+            "desaturate" => Ok(ColorCorrection::Desaturate),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown color correction: {}", name),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cartridge(title: &[u8]) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14B] = 0x01; // Nintendo
+        Cartridge::from_rom(rom)
+    }
+
+    #[test]
+    fn picks_palette_by_title_checksum() {
+        assert_eq!(
+            PaletteSet::for_cartridge(&cartridge(b"POKEMON RED")),
+            PaletteSet::preset("up-a").unwrap()
+        );
+        assert_eq!(
+            PaletteSet::for_cartridge(&cartridge(b"POKEMON BLUE")),
+            PaletteSet::preset("left").unwrap()
+        );
+    }
+
+    #[test]
+    fn shared_checksum_needs_fourth_letter() {
+        // Same checksum as POKEMON BLUE, different fourth letter
+        assert_eq!(
+            title_checksum(b"POKAMON BLUI"),
+            title_checksum(b"POKEMON BLUE")
+        );
+        assert_eq!(
+            PaletteSet::for_cartridge(&cartridge(b"POKAMON BLUI")),
+            PaletteSet::preset("right-a").unwrap()
+        );
+    }
+}
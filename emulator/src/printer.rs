@@ -0,0 +1,408 @@
+use std::{cell::RefCell, collections::VecDeque, io, path::PathBuf, rc::Rc};
+
+use crate::{
+    constants::SCREEN_WIDTH,
+    gpu::{DmgColor, FrameBuffer},
+    serial::SerialLink,
+};
+
+const MAGIC_1: u8 = 0x88;
+const MAGIC_2: u8 = 0x33;
+/// First byte of the printer's reply, telling a printer is connected
+const ALIVE: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 1;
+const STATUS_PRINTING: u8 = 1 << 1;
+const STATUS_IMAGE_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED_DATA: u8 = 1 << 3;
+
+const RLE_RUN_BITMASK: u8 = 0x80;
+
+const TILE_LEN: usize = 16;
+const TILES_PER_ROW: usize = SCREEN_WIDTH / 8;
+/// DATA packet of two tile rows
+const DATA_PACKET_LEN: usize = 2 * TILES_PER_ROW * TILE_LEN;
+/// Image memory of the printer, 9 DATA packets
+const IMAGE_BUFFER_LEN: usize = 9 * DATA_PACKET_LEN;
+
+/// STATUS requests answered with the printing bit after a PRINT
+const PRINTING_STATUS_POLLS: u8 = 4;
+
+/// Outcome of each finished print job, the PNG file written or the error
+/// writing it
+pub type PrintResults = Rc<RefCell<VecDeque<io::Result<PathBuf>>>>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// Game Boy Printer on the serial port. Receives packets of the form
+/// `88 33 command compression length data checksum`, answers each with
+/// `81 status` and renders the tile data it gets printed. A print job ends
+/// with a PRINT that has a margin after it; its paper is then written to a
+/// PNG file.
+pub struct Printer {
+    output_dir: PathBuf,
+    pages: usize,
+    results: PrintResults,
+    state: State,
+    /// Bits received of the current byte
+    bits: u8,
+    received: u8,
+    reply: u8,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    packet_checksum: u16,
+    status: u8,
+    printing_polls: u8,
+    image: Vec<u8>,
+    /// Pixel rows printed in the current job
+    paper: Vec<[DmgColor; SCREEN_WIDTH]>,
+}
+
+impl Printer {
+    /// Printed pages are written to `output_dir` as `print_0001.png` and
+    /// onwards.
+    pub fn new<P: Into<PathBuf>>(output_dir: P) -> Self {
+        Printer {
+            output_dir: output_dir.into(),
+            pages: 0,
+            results: PrintResults::default(),
+            state: State::Magic1,
+            bits: 0,
+            received: 0,
+            reply: 0,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            packet_checksum: 0,
+            status: 0,
+            printing_polls: 0,
+            image: Vec::new(),
+            paper: Vec::new(),
+        }
+    }
+
+    /// Where finished print jobs are reported, to be taken out by the
+    /// frontend.
+    pub fn results(&self) -> PrintResults {
+        self.results.clone()
+    }
+
+    /// Handles a complete byte, returns the byte to reply with during the
+    /// next one.
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut reply = 0;
+
+        self.state = match self.state {
+            State::Magic1 if byte == MAGIC_1 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if byte == MAGIC_2 => State::Command,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.packet_checksum = byte as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.packet_checksum |= (byte as u16) << 8;
+                reply = ALIVE;
+                State::Alive
+            }
+            State::Alive => {
+                self.handle_packet();
+                reply = self.status;
+                State::Status
+            }
+            State::Status => State::Magic1,
+        };
+
+        // A new packet may start right away
+        if self.state == State::Magic1 && byte == MAGIC_1 {
+            self.state = State::Magic2;
+        }
+
+        reply
+    }
+
+    fn handle_packet(&mut self) {
+        if self.checksum != self.packet_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+            }
+            COMMAND_DATA => {
+                let data = std::mem::take(&mut self.data);
+                if self.compressed {
+                    decompress(&data, &mut self.image);
+                } else {
+                    self.image.extend_from_slice(&data);
+                }
+                self.image.truncate(IMAGE_BUFFER_LEN);
+
+                self.status |= STATUS_UNPROCESSED_DATA;
+                if self.image.len() == IMAGE_BUFFER_LEN {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            COMMAND_PRINT if self.data.len() == 4 => {
+                let (margins, palette) = (self.data[1], self.data[2]);
+                self.print(margins, palette);
+                self.status = STATUS_PRINTING;
+                self.printing_polls = PRINTING_STATUS_POLLS;
+            }
+            COMMAND_STATUS if self.printing_polls > 0 => {
+                self.printing_polls -= 1;
+                if self.printing_polls == 0 {
+                    self.status &= !STATUS_PRINTING;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Prints the image memory with the given palette. The low nibble of
+    /// `margins` is the paper fed after the image; a margin ends the job.
+    fn print(&mut self, margins: u8, palette: u8) {
+        let shades = [
+            DmgColor::White,
+            DmgColor::LightGrey,
+            DmgColor::DarkGrey,
+            DmgColor::Black,
+        ];
+        let tile_rows = self.image.len() / (TILE_LEN * TILES_PER_ROW);
+
+        for tile_row in 0..tile_rows {
+            for line in 0..8 {
+                let mut row = [DmgColor::White; SCREEN_WIDTH];
+
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let tile = tile_row * TILES_PER_ROW + x / 8;
+                    let address = tile * TILE_LEN + line * 2;
+                    let bit = 7 - (x % 8);
+                    let color = ((self.image[address] >> bit) & 1)
+                        | (((self.image[address + 1] >> bit) & 1) << 1);
+
+                    *pixel = shades[((palette >> (color * 2)) & 0b11) as usize];
+                }
+
+                self.paper.push(row);
+            }
+        }
+        self.image.clear();
+
+        if margins & 0x0F != 0 {
+            self.finish_page();
+        }
+    }
+
+    fn finish_page(&mut self) {
+        if self.paper.is_empty() {
+            return;
+        }
+
+        let mut frame = FrameBuffer::new(SCREEN_WIDTH, self.paper.len());
+        for (y, row) in self.paper.drain(..).enumerate() {
+            for (x, color) in row.into_iter().enumerate() {
+                frame.set_pixel(x, y, color);
+            }
+        }
+
+        self.pages += 1;
+        let path = self.output_dir.join(format!("print_{:04}.png", self.pages));
+        let result = frame.save_png(&path).map(|_| path);
+        self.results.borrow_mut().push_back(result);
+    }
+}
+
+/// Expands the printer's run length encoding: a byte with the top bit set
+/// repeats the next byte `(n & 0x7F) + 2` times, otherwise `n + 1` bytes
+/// follow as they are.
+fn decompress(data: &[u8], output: &mut Vec<u8>) {
+    let mut bytes = data.iter();
+
+    while let Some(&control) = bytes.next() {
+        if control & RLE_RUN_BITMASK != 0 {
+            if let Some(&value) = bytes.next() {
+                let count = (control & !RLE_RUN_BITMASK) as usize + 2;
+                // `repeat_n` needs Rust 1.82
+                #[allow(clippy::manual_repeat_n)]
+                output.extend(std::iter::repeat(value).take(count));
+            }
+        } else {
+            output.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+}
+
+impl SerialLink for Printer {
+    fn exchange(&mut self, bit: bool) -> bool {
+        let reply_bit = self.reply & 0x80 != 0;
+        self.reply <<= 1;
+
+        self.received = (self.received << 1) | bit as u8;
+        self.bits += 1;
+
+        if self.bits == 8 {
+            self.reply = self.receive(self.received);
+            self.bits = 0;
+            self.received = 0;
+        }
+
+        reply_bit
+    }
+
+    /// The printer never drives the clock.
+    fn poll(&mut self, _: Option<u8>) -> Option<bool> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(printer: &mut Printer, byte: u8) -> u8 {
+        (0..8).rev().fold(0, |reply, bit| {
+            (reply << 1) | printer.exchange(byte & (1 << bit) != 0) as u8
+        })
+    }
+
+    /// Sends a packet, returns the two reply bytes.
+    fn send_packet(
+        printer: &mut Printer,
+        command: u8,
+        data: &[u8],
+        checksum_error: bool,
+    ) -> (u8, u8) {
+        let length = (data.len() as u16).to_le_bytes();
+        let header = [command, 0, length[0], length[1]];
+        let mut checksum = header
+            .iter()
+            .chain(data)
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        if checksum_error {
+            checksum = !checksum;
+        }
+
+        for &byte in [MAGIC_1, MAGIC_2].iter().chain(&header).chain(data) {
+            assert_eq!(transfer(printer, byte), 0);
+        }
+        for byte in checksum.to_le_bytes() {
+            transfer(printer, byte);
+        }
+
+        (transfer(printer, 0), transfer(printer, 0))
+    }
+
+    #[test]
+    fn prints_data_packet() {
+        let output_dir = std::env::temp_dir().join(format!("printer-test-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let mut printer = Printer::new(&output_dir);
+        let results = printer.results();
+
+        assert_eq!(
+            send_packet(&mut printer, COMMAND_INIT, &[], false),
+            (ALIVE, 0)
+        );
+        assert_eq!(
+            send_packet(&mut printer, COMMAND_STATUS, &[], true),
+            (ALIVE, STATUS_CHECKSUM_ERROR)
+        );
+        assert_eq!(
+            send_packet(&mut printer, COMMAND_DATA, &[0xFF; DATA_PACKET_LEN], false),
+            (ALIVE, STATUS_UNPROCESSED_DATA)
+        );
+
+        // One sheet, a margin after the image, default palette
+        let print = [1, 0x01, 0xE4, 0x40];
+        assert_eq!(
+            send_packet(&mut printer, COMMAND_PRINT, &print, false),
+            (ALIVE, STATUS_PRINTING)
+        );
+        for _ in 1..PRINTING_STATUS_POLLS {
+            assert_eq!(
+                send_packet(&mut printer, COMMAND_STATUS, &[], false),
+                (ALIVE, STATUS_PRINTING)
+            );
+        }
+        assert_eq!(
+            send_packet(&mut printer, COMMAND_STATUS, &[], false),
+            (ALIVE, 0)
+        );
+
+        let path = results.borrow_mut().pop_front().unwrap().unwrap();
+        assert_eq!(path, output_dir.join("print_0001.png"));
+        assert!(path.exists());
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn decompresses_runs_and_literals() {
+        let mut output = Vec::new();
+        decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34], &mut output);
+
+        assert_eq!(output, [0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+    }
+}
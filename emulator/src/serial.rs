@@ -0,0 +1,296 @@
+use crate::{bus::FetchWrite, interrupts::Interrupts};
+
+const SB_REGISTER_ADDRESS: u16 = 0xFF01;
+const SC_REGISTER_ADDRESS: u16 = 0xFF02;
+
+const TRANSFER_START_BITMASK: u8 = 1 << 7;
+const FAST_CLOCK_BITMASK: u8 = 1 << 1;
+const INTERNAL_CLOCK_BITMASK: u8 = 1;
+const SC_UNUSED_BITMASK: u8 = 0x7E;
+const SC_CGB_UNUSED_BITMASK: u8 = 0x7C;
+
+/// Clock cycles per bit with the internal clock: 8192 Hz, 262144 Hz in CGB
+/// fast mode
+const BIT_CYCLES: u32 = 512;
+const FAST_BIT_CYCLES: u32 = 16;
+
+/// The other end of the link cable.
+pub trait SerialLink {
+    /// Shifts `bit` out to the peer while this Game Boy drives the clock.
+    /// Returns the bit the peer shifted out in exchange, 1 if nobody
+    /// answers.
+    fn exchange(&mut self, bit: bool) -> bool;
+
+    /// Called every step with SB while this Game Boy waits for the peer's
+    /// clock, `None` while it doesn't. Its top bit is shifted out next.
+    /// Returns the bit of a clock pulse from the peer, if one arrived.
+    fn poll(&mut self, sb: Option<u8>) -> Option<bool>;
+
+    /// Called every step with the clock cycles that passed, e.g. to keep a
+    /// remote peer in sync.
+    fn next(&mut self, _clock_cycles: u8) {}
+}
+
+/// Serial port: SB is shifted out from the top while the peer's bits are
+/// shifted in from the bottom. After 8 bits the transfer ends and the
+/// serial interrupt is requested.
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cgb: bool,
+    bits_left: u8,
+    cycles: u32,
+    link: Option<Box<dyn SerialLink>>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+            cgb: false,
+            bits_left: 0,
+            cycles: 0,
+            link: None,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    pub fn set_link(&mut self, link: Option<Box<dyn SerialLink>>) {
+        self.link = link;
+    }
+
+    pub fn take_link(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.link.take()
+    }
+
+    fn is_transferring(&self) -> bool {
+        self.sc & TRANSFER_START_BITMASK != 0
+    }
+
+    fn bit_cycles(&self) -> u32 {
+        if self.cgb && self.sc & FAST_CLOCK_BITMASK != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            BIT_CYCLES
+        }
+    }
+
+    pub fn next(&mut self, clock_cycles: u8, interrupts: &mut Interrupts) {
+        if let Some(link) = self.link.as_mut() {
+            link.next(clock_cycles);
+        }
+
+        if !self.is_transferring() {
+            if let Some(link) = self.link.as_mut() {
+                link.poll(None);
+            }
+            return;
+        }
+
+        if self.sc & INTERNAL_CLOCK_BITMASK != 0 {
+            self.cycles += clock_cycles as u32;
+
+            while self.is_transferring() && self.cycles >= self.bit_cycles() {
+                self.cycles -= self.bit_cycles();
+
+                let bit = self.sb & 0x80 != 0;
+                let received = match self.link.as_mut() {
+                    Some(link) => link.exchange(bit),
+                    None => true,
+                };
+                self.shift(received, interrupts);
+            }
+        } else if let Some(link) = self.link.as_mut() {
+            if let Some(received) = link.poll(Some(self.sb)) {
+                self.shift(received, interrupts);
+            }
+        }
+    }
+
+    fn shift(&mut self, bit: bool, interrupts: &mut Interrupts) {
+        self.sb = (self.sb << 1) | bit as u8;
+        self.bits_left -= 1;
+
+        if self.bits_left == 0 {
+            self.sc &= !TRANSFER_START_BITMASK;
+            interrupts.set_serial_request(true);
+        }
+    }
+}
+
+impl FetchWrite for Serial {
+    fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        let unused = if self.cgb {
+            SC_CGB_UNUSED_BITMASK
+        } else {
+            SC_UNUSED_BITMASK
+        };
+
+        let value = match address {
+            SB_REGISTER_ADDRESS => self.sb,
+            SC_REGISTER_ADDRESS => self.sc | unused,
+            _ => panic!("Accessing unsupported serial address: {:#X}", address),
+        };
+
+        Ok(value)
+    }
+
+    fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+
+    fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        match address {
+            SB_REGISTER_ADDRESS => self.sb = value,
+            SC_REGISTER_ADDRESS => {
+                self.sc = value;
+                if self.is_transferring() {
+                    self.bits_left = 8;
+                    self.cycles = 0;
+                }
+            }
+            _ => panic!("Accessing unsupported serial address: {:#X}", address),
+        }
+
+        Ok(())
+    }
+
+    fn write16(&mut self, _: u16, _: u16) -> std::io::Result<()> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Answers exchanges with its own bits and sends clock pulses from a
+    /// queue. Hands out `None` pulses while the queue is empty.
+    struct ScriptedPeer {
+        pulses: VecDeque<Option<bool>>,
+        answer: u8,
+    }
+
+    impl SerialLink for ScriptedPeer {
+        fn exchange(&mut self, _bit: bool) -> bool {
+            let bit = self.answer & 0x80 != 0;
+            self.answer <<= 1;
+            bit
+        }
+
+        fn poll(&mut self, sb: Option<u8>) -> Option<bool> {
+            sb?;
+            self.pulses.pop_front().flatten()
+        }
+    }
+
+    fn serial(cgb: bool, link: Option<ScriptedPeer>) -> Serial {
+        let mut serial = Serial::new();
+        serial.set_cgb_mode(cgb);
+        serial.set_link(link.map(|link| Box::new(link) as Box<dyn SerialLink>));
+        serial
+    }
+
+    /// Runs the serial port in M-cycles, returns the cycles until the
+    /// transfer ended, if it did.
+    fn run_transfer(serial: &mut Serial, interrupts: &mut Interrupts, limit: u32) -> Option<u32> {
+        for cycles in (4..=limit).step_by(4) {
+            serial.next(4, interrupts);
+            if interrupts.serial_request() {
+                return Some(cycles);
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn internal_clock_shifts_in_ones_without_peer() {
+        let mut serial = serial(false, None);
+        let mut interrupts = Interrupts::new();
+        serial.write8(SB_REGISTER_ADDRESS, 0x42).unwrap();
+        serial.write8(SC_REGISTER_ADDRESS, 0x81).unwrap();
+
+        let cycles = run_transfer(&mut serial, &mut interrupts, 10000);
+
+        assert_eq!(cycles, Some(8 * BIT_CYCLES));
+        assert_eq!(serial.fetch8(SB_REGISTER_ADDRESS).unwrap(), 0xFF);
+        assert_eq!(serial.fetch8(SC_REGISTER_ADDRESS).unwrap(), 0x7F);
+    }
+
+    #[test]
+    fn internal_clock_exchanges_with_peer() {
+        let peer = ScriptedPeer {
+            pulses: VecDeque::new(),
+            answer: 0x5A,
+        };
+        let mut serial = serial(false, Some(peer));
+        let mut interrupts = Interrupts::new();
+        serial.write8(SB_REGISTER_ADDRESS, 0x42).unwrap();
+        serial.write8(SC_REGISTER_ADDRESS, 0x81).unwrap();
+
+        run_transfer(&mut serial, &mut interrupts, 10000).unwrap();
+
+        assert_eq!(serial.fetch8(SB_REGISTER_ADDRESS).unwrap(), 0x5A);
+    }
+
+    #[test]
+    fn fast_clock_only_on_cgb() {
+        let mut interrupts = Interrupts::new();
+
+        let mut cgb = serial(true, None);
+        cgb.write8(SC_REGISTER_ADDRESS, 0x83).unwrap();
+        assert_eq!(
+            run_transfer(&mut cgb, &mut interrupts, 10000),
+            Some(8 * FAST_BIT_CYCLES)
+        );
+        assert_eq!(cgb.fetch8(SC_REGISTER_ADDRESS).unwrap(), 0x7F);
+
+        interrupts.set_serial_request(false);
+        let mut dmg = serial(false, None);
+        dmg.write8(SC_REGISTER_ADDRESS, 0x83).unwrap();
+        assert_eq!(
+            run_transfer(&mut dmg, &mut interrupts, 10000),
+            Some(8 * BIT_CYCLES)
+        );
+    }
+
+    #[test]
+    fn external_clock_shifts_on_peer_pulses() {
+        // The peer is late with its first pulse
+        let mut pulses = VecDeque::from([None, None]);
+        pulses.extend([true, false, true, false, true, false, true, false].map(Some));
+        let peer = ScriptedPeer { pulses, answer: 0 };
+        let mut serial = serial(false, Some(peer));
+        let mut interrupts = Interrupts::new();
+        serial.write8(SB_REGISTER_ADDRESS, 0x0F).unwrap();
+        serial.write8(SC_REGISTER_ADDRESS, 0x80).unwrap();
+
+        let cycles = run_transfer(&mut serial, &mut interrupts, 10000);
+
+        assert_eq!(cycles, Some(10 * 4));
+        assert_eq!(serial.fetch8(SB_REGISTER_ADDRESS).unwrap(), 0xAA);
+    }
+
+    #[test]
+    fn external_clock_waits_without_peer() {
+        let mut serial = serial(false, None);
+        let mut interrupts = Interrupts::new();
+        serial.write8(SC_REGISTER_ADDRESS, 0x80).unwrap();
+
+        assert_eq!(run_transfer(&mut serial, &mut interrupts, 10000), None);
+        assert_eq!(serial.fetch8(SC_REGISTER_ADDRESS).unwrap(), 0xFE);
+    }
+}
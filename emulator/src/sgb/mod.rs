@@ -0,0 +1,499 @@
+use crate::{
+    constants::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    gpu::{DmgColor, FrameBuffer},
+};
+
+pub use self::packet::PacketReader;
+
+mod packet;
+
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+
+/// Position of the Game Boy screen inside the SGB picture
+const GAME_SCREEN_X: usize = 48;
+const GAME_SCREEN_Y: usize = 40;
+
+const ATTRIBUTE_COLUMNS: usize = SCREEN_WIDTH / 8;
+const ATTRIBUTE_ROWS: usize = SCREEN_HEIGHT / 8;
+const ATTRIBUTE_FILE_LEN: usize = ATTRIBUTE_COLUMNS * ATTRIBUTE_ROWS / 4;
+const ATTRIBUTE_FILES: usize = 45;
+
+const SYSTEM_PALETTES: usize = 512;
+
+const BORDER_COLUMNS: usize = SGB_SCREEN_WIDTH / 8;
+const BORDER_ROWS: usize = SGB_SCREEN_HEIGHT / 8;
+const BORDER_MAP_LEN: usize = 32 * 32;
+const BORDER_TILES: usize = 256;
+const BORDER_TILE_LEN: usize = 32;
+const BORDER_PALETTE_OFFSET: usize = 0x800;
+const FIRST_BORDER_PALETTE: usize = 4;
+
+/// Size of a VRAM transfer (CHR_TRN, PCT_TRN, PAL_TRN, ATTR_TRN)
+pub const TRANSFER_LEN: usize = 0x1000;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+pub const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+/// Palette the SGB starts up with
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+pub fn command_code(command: &[u8]) -> u8 {
+    command[0] >> 3
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    None,
+    Freeze,
+    Black,
+    Color0,
+}
+
+/// Super Game Boy state driven by command packets: the four screen
+/// palettes and the attribute map assigning them to 8x8 cells, the screen
+/// mask and the border.
+pub struct Sgb {
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>,
+    attributes: [u8; ATTRIBUTE_COLUMNS * ATTRIBUTE_ROWS],
+    attribute_files: Vec<u8>,
+    mask: Mask,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    border_palettes: [[u16; 16]; 4],
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; SYSTEM_PALETTES * 4],
+            attributes: [0; ATTRIBUTE_COLUMNS * ATTRIBUTE_ROWS],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_LEN],
+            mask: Mask::None,
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_LEN],
+            border_map: vec![0; BORDER_MAP_LEN],
+            border_palettes: [[0; 16]; 4],
+        }
+    }
+
+    /// Whether the command reads a block of data from VRAM.
+    pub fn is_transfer_command(command: &[u8]) -> bool {
+        matches!(
+            command_code(command),
+            PAL_TRN | CHR_TRN | PCT_TRN | ATTR_TRN
+        )
+    }
+
+    /// Executes a complete command. `transfer` holds the VRAM block shown
+    /// on screen for transfer commands.
+    pub fn handle_command(&mut self, command: &[u8], transfer: &[u8]) {
+        match command_code(command) {
+            PAL01 => self.set_palette_pair(command, 0, 1),
+            PAL23 => self.set_palette_pair(command, 2, 3),
+            PAL03 => self.set_palette_pair(command, 0, 3),
+            PAL12 => self.set_palette_pair(command, 1, 2),
+            ATTR_BLK => self.attr_blk(command),
+            ATTR_LIN => self.attr_lin(command),
+            ATTR_DIV => self.attr_div(command),
+            ATTR_CHR => self.attr_chr(command),
+            PAL_SET => self.pal_set(command),
+            PAL_TRN => {
+                for (index, color) in self.system_palettes.iter_mut().enumerate() {
+                    *color = u16::from_le_bytes([transfer[index * 2], transfer[index * 2 + 1]]);
+                }
+            }
+            CHR_TRN => {
+                let offset = (command[1] & 1) as usize * TRANSFER_LEN;
+                self.border_tiles[offset..offset + TRANSFER_LEN].copy_from_slice(transfer);
+            }
+            PCT_TRN => self.pct_trn(transfer),
+            ATTR_TRN => {
+                let len = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&transfer[..len]);
+            }
+            ATTR_SET => {
+                self.load_attribute_file(command[1] & 0x3F);
+                if command[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            MASK_EN => {
+                self.mask = match command[1] & 0b11 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::None,
+                }
+            }
+            // MLT_REQ is handled by the joypad, everything else is unsupported
+            _ => {}
+        }
+    }
+
+    fn set_palette_pair(&mut self, command: &[u8], first: usize, second: usize) {
+        let color =
+            |index: usize| u16::from_le_bytes([command[1 + index * 2], command[2 + index * 2]]);
+
+        // Color 0 is shared by all palettes
+        self.palettes[0][0] = color(0);
+        for index in 1..4 {
+            self.palettes[first][index] = color(index);
+            self.palettes[second][index] = color(index + 3);
+        }
+    }
+
+    fn attr_blk(&mut self, command: &[u8]) {
+        let data_sets = (command[1] & 0x1F) as usize;
+
+        for data_set in command[2..].chunks_exact(6).take(data_sets) {
+            let control = data_set[0];
+            let change_inside = control & 1 != 0;
+            let mut change_border = control & 2 != 0;
+            let change_outside = control & 4 != 0;
+
+            let palette_inside = data_set[1] & 0b11;
+            let mut palette_border = (data_set[1] >> 2) & 0b11;
+            let palette_outside = (data_set[1] >> 4) & 0b11;
+
+            // With only one of inside/outside set, the border follows it
+            if change_inside && !change_outside && !change_border {
+                change_border = true;
+                palette_border = palette_inside;
+            } else if change_outside && !change_inside && !change_border {
+                change_border = true;
+                palette_border = palette_outside;
+            }
+
+            let x1 = (data_set[2] & 0x1F) as usize;
+            let y1 = (data_set[3] & 0x1F) as usize;
+            let x2 = (data_set[4] & 0x1F) as usize;
+            let y2 = (data_set[5] & 0x1F) as usize;
+
+            for y in 0..ATTRIBUTE_ROWS {
+                for x in 0..ATTRIBUTE_COLUMNS {
+                    let inside_x = x > x1 && x < x2;
+                    let inside_y = y > y1 && y < y2;
+                    let on_x = x >= x1 && x <= x2;
+                    let on_y = y >= y1 && y <= y2;
+
+                    let palette = if inside_x && inside_y {
+                        change_inside.then_some(palette_inside)
+                    } else if on_x && on_y {
+                        change_border.then_some(palette_border)
+                    } else {
+                        change_outside.then_some(palette_outside)
+                    };
+
+                    if let Some(palette) = palette {
+                        self.attributes[y * ATTRIBUTE_COLUMNS + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, command: &[u8]) {
+        let lines = command[1] as usize;
+
+        for &line in command[2..].iter().take(lines) {
+            let number = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0b11;
+            let horizontal = line & 0x80 != 0;
+
+            if horizontal && number < ATTRIBUTE_ROWS {
+                for x in 0..ATTRIBUTE_COLUMNS {
+                    self.attributes[number * ATTRIBUTE_COLUMNS + x] = palette;
+                }
+            } else if !horizontal && number < ATTRIBUTE_COLUMNS {
+                for y in 0..ATTRIBUTE_ROWS {
+                    self.attributes[y * ATTRIBUTE_COLUMNS + number] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, command: &[u8]) {
+        let palette_after = command[1] & 0b11;
+        let palette_before = (command[1] >> 2) & 0b11;
+        let palette_on = (command[1] >> 4) & 0b11;
+        let horizontal = command[1] & 0x40 != 0;
+        let coordinate = (command[2] & 0x1F) as usize;
+
+        for y in 0..ATTRIBUTE_ROWS {
+            for x in 0..ATTRIBUTE_COLUMNS {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * ATTRIBUTE_COLUMNS + x] = match position.cmp(&coordinate) {
+                    std::cmp::Ordering::Less => palette_before,
+                    std::cmp::Ordering::Equal => palette_on,
+                    std::cmp::Ordering::Greater => palette_after,
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, command: &[u8]) {
+        let mut x = (command[1] & 0x1F) as usize;
+        let mut y = (command[2] & 0x1F) as usize;
+        let count = u16::from_le_bytes([command[3], command[4]]) as usize;
+        let top_to_bottom = command[5] & 1 != 0;
+
+        for index in 0..count {
+            let Some(&data) = command.get(6 + index / 4) else {
+                break;
+            };
+            if x >= ATTRIBUTE_COLUMNS || y >= ATTRIBUTE_ROWS {
+                break;
+            }
+
+            let palette = (data >> (6 - (index % 4) * 2)) & 0b11;
+            self.attributes[y * ATTRIBUTE_COLUMNS + x] = palette;
+
+            if top_to_bottom {
+                y += 1;
+                if y == ATTRIBUTE_ROWS {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == ATTRIBUTE_COLUMNS {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, command: &[u8]) {
+        for palette in 0..4 {
+            let number =
+                u16::from_le_bytes([command[1 + palette * 2], command[2 + palette * 2]]) as usize;
+            let offset = (number % SYSTEM_PALETTES) * 4;
+            self.palettes[palette].copy_from_slice(&self.system_palettes[offset..offset + 4]);
+        }
+
+        let flags = command[9];
+        if flags & 0x80 != 0 {
+            self.load_attribute_file(flags & 0x3F);
+        }
+        if flags & 0x40 != 0 {
+            self.mask = Mask::None;
+        }
+    }
+
+    fn pct_trn(&mut self, transfer: &[u8]) {
+        for (index, entry) in self.border_map.iter_mut().enumerate() {
+            *entry = u16::from_le_bytes([transfer[index * 2], transfer[index * 2 + 1]]);
+        }
+
+        for (palette, colors) in self.border_palettes.iter_mut().enumerate() {
+            for (index, color) in colors.iter_mut().enumerate() {
+                let offset = BORDER_PALETTE_OFFSET + (palette * 16 + index) * 2;
+                *color = u16::from_le_bytes([transfer[offset], transfer[offset + 1]]);
+            }
+        }
+    }
+
+    fn load_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file >= ATTRIBUTE_FILES {
+            return;
+        }
+
+        let data = &self.attribute_files[file * ATTRIBUTE_FILE_LEN..][..ATTRIBUTE_FILE_LEN];
+        for (index, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = (data[index / 4] >> (6 - (index % 4) * 2)) & 0b11;
+        }
+    }
+
+    /// Colorizes a finished Game Boy frame and places it inside the border.
+    pub fn compose(&self, frame: &FrameBuffer, output: &mut FrameBuffer) {
+        let backdrop = DmgColor::from_rgb555(self.palettes[0][0]);
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Freeze => continue,
+                    Mask::Black => DmgColor::Black,
+                    Mask::Color0 => backdrop,
+                    Mask::None => {
+                        let palette = self.attributes[(y / 8) * ATTRIBUTE_COLUMNS + x / 8];
+                        let shade = match frame.get_pixel(x, y) {
+                            DmgColor::White => 0,
+                            DmgColor::LightGrey => 1,
+                            DmgColor::DarkGrey => 2,
+                            _ => 3,
+                        };
+                        match shade {
+                            0 => backdrop,
+                            _ => DmgColor::from_rgb555(self.palettes[palette as usize][shade]),
+                        }
+                    }
+                };
+
+                output.set_pixel(GAME_SCREEN_X + x, GAME_SCREEN_Y + y, color);
+            }
+        }
+
+        self.draw_border(output, backdrop);
+    }
+
+    fn draw_border(&self, output: &mut FrameBuffer, backdrop: DmgColor) {
+        for row in 0..BORDER_ROWS {
+            for column in 0..BORDER_COLUMNS {
+                let entry = self.border_map[row * 32 + column];
+                let tile = (entry & 0xFF) as usize;
+                let palette =
+                    (((entry >> 10) & 0b111) as usize).saturating_sub(FIRST_BORDER_PALETTE);
+                let flip_x = entry & 0x4000 != 0;
+                let flip_y = entry & 0x8000 != 0;
+
+                for tile_y in 0..8 {
+                    for tile_x in 0..8 {
+                        let x = column * 8 + tile_x;
+                        let y = row * 8 + tile_y;
+                        let pixel_x = if flip_x { 7 - tile_x } else { tile_x };
+                        let pixel_y = if flip_y { 7 - tile_y } else { tile_y };
+                        let color = self.get_border_tile_pixel(tile, pixel_x, pixel_y);
+
+                        if color != 0 {
+                            let rgb555 = self.border_palettes[palette][color as usize];
+                            output.set_pixel(x, y, DmgColor::from_rgb555(rgb555));
+                        } else if !is_game_screen(x, y) {
+                            output.set_pixel(x, y, backdrop);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Border tiles use the 4 bits per pixel SNES format: bit planes 0/1
+    /// interleaved in the first 16 bytes, planes 2/3 in the last 16.
+    fn get_border_tile_pixel(&self, tile: usize, x: usize, y: usize) -> u8 {
+        let data = &self.border_tiles[tile * BORDER_TILE_LEN..][..BORDER_TILE_LEN];
+        let bit = 7 - x;
+        let plane = |offset: usize| (data[offset] >> bit) & 1;
+
+        plane(y * 2)
+            | (plane(y * 2 + 1) << 1)
+            | (plane(16 + y * 2) << 2)
+            | (plane(16 + y * 2 + 1) << 3)
+    }
+}
+
+fn is_game_screen(x: usize, y: usize) -> bool {
+    (GAME_SCREEN_X..GAME_SCREEN_X + SCREEN_WIDTH).contains(&x)
+        && (GAME_SCREEN_Y..GAME_SCREEN_Y + SCREEN_HEIGHT).contains(&y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a one packet command from its parameters.
+    fn command(code: u8, parameters: &[u8]) -> Vec<u8> {
+        let mut command = vec![0; 16];
+        command[0] = (code << 3) | 1;
+        command[1..=parameters.len()].copy_from_slice(parameters);
+
+        command
+    }
+
+    fn attribute(sgb: &Sgb, x: usize, y: usize) -> u8 {
+        sgb.attributes[y * ATTRIBUTE_COLUMNS + x]
+    }
+
+    #[test]
+    fn attr_blk_sets_inside_border_and_outside() {
+        let mut sgb = Sgb::new();
+        // Inside 1, border 2, outside 3 for the cells (2, 3) to (5, 6)
+        sgb.handle_command(&command(ATTR_BLK, &[1, 0b111, 0x39, 2, 3, 5, 6]), &[]);
+
+        assert_eq!(attribute(&sgb, 3, 4), 1);
+        assert_eq!(attribute(&sgb, 4, 5), 1);
+        assert_eq!(attribute(&sgb, 2, 3), 2);
+        assert_eq!(attribute(&sgb, 5, 4), 2);
+        assert_eq!(attribute(&sgb, 3, 6), 2);
+        assert_eq!(attribute(&sgb, 0, 0), 3);
+        assert_eq!(attribute(&sgb, 6, 4), 3);
+    }
+
+    #[test]
+    fn attr_blk_border_follows_inside() {
+        let mut sgb = Sgb::new();
+        sgb.handle_command(&command(ATTR_BLK, &[1, 0b001, 0x02, 2, 3, 5, 6]), &[]);
+
+        assert_eq!(attribute(&sgb, 3, 4), 2);
+        assert_eq!(attribute(&sgb, 2, 3), 2);
+        assert_eq!(attribute(&sgb, 0, 0), 0);
+    }
+
+    #[test]
+    fn attr_lin_sets_rows_and_columns() {
+        let mut sgb = Sgb::new();
+        // Row 4 to palette 1, then column 7 to palette 2
+        sgb.handle_command(&command(ATTR_LIN, &[2, 0xA4, 0x47]), &[]);
+
+        assert_eq!(attribute(&sgb, 0, 4), 1);
+        assert_eq!(attribute(&sgb, 19, 4), 1);
+        assert_eq!(attribute(&sgb, 7, 0), 2);
+        assert_eq!(attribute(&sgb, 7, 4), 2);
+        assert_eq!(attribute(&sgb, 0, 0), 0);
+    }
+
+    #[test]
+    fn attr_div_splits_the_screen() {
+        let mut sgb = Sgb::new();
+        // Horizontal at row 9: before 2, on 3, after 1
+        sgb.handle_command(&command(ATTR_DIV, &[0x79, 9]), &[]);
+
+        assert_eq!(attribute(&sgb, 0, 8), 2);
+        assert_eq!(attribute(&sgb, 19, 9), 3);
+        assert_eq!(attribute(&sgb, 10, 10), 1);
+    }
+
+    #[test]
+    fn pal01_colors_cells_by_attribute() {
+        let mut sgb = Sgb::new();
+        let colors: [u16; 7] = [0x7FFF, 0x001F, 0x03E0, 0x7C00, 0x0011, 0x0220, 0x4400];
+        let parameters: Vec<u8> = colors
+            .iter()
+            .flat_map(|color| color.to_le_bytes())
+            .collect();
+        sgb.handle_command(&command(PAL01, &parameters), &[]);
+        // Vertical at column 10: palette 0 before, palette 1 on and after
+        sgb.handle_command(&command(ATTR_DIV, &[0x11, 10]), &[]);
+
+        let mut frame = FrameBuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        frame.clear(DmgColor::DarkGrey);
+        frame.set_pixel(0, 0, DmgColor::White);
+        frame.set_pixel(SCREEN_WIDTH - 1, 0, DmgColor::Black);
+        let mut output = FrameBuffer::new(SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT);
+        sgb.compose(&frame, &mut output);
+
+        let pixel = |x: usize, y: usize| output.get_pixel(GAME_SCREEN_X + x, GAME_SCREEN_Y + y);
+        // Color 0 is shared by all palettes
+        assert_eq!(pixel(0, 0), DmgColor::from_rgb555(colors[0]));
+        assert_eq!(pixel(0, 1), DmgColor::from_rgb555(colors[2]));
+        assert_eq!(pixel(79, 0), DmgColor::from_rgb555(colors[2]));
+        assert_eq!(pixel(80, 0), DmgColor::from_rgb555(colors[5]));
+        assert_eq!(pixel(SCREEN_WIDTH - 1, 0), DmgColor::from_rgb555(colors[6]));
+    }
+}
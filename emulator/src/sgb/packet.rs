@@ -0,0 +1,194 @@
+const PACKET_LEN: usize = 16;
+const PACKET_BITS: usize = PACKET_LEN * 8;
+
+const PACKET_COUNT_BITMASK: u8 = 0b111;
+
+const SELECT_BITMASK: u8 = 0x30;
+const PULSE_RESET: u8 = 0x00;
+const PULSE_ZERO: u8 = 0x20;
+const PULSE_ONE: u8 = 0x10;
+const PULSE_IDLE: u8 = 0x30;
+
+/// Decodes SGB command packets sent by pulsing P14/P15.
+///
+/// A packet starts with both lines pulled low, followed by 128 data bits
+/// (P14 low for 0, P15 low for 1, LSB first) and a 0 stop bit. Each pulse
+/// is separated by both lines going high again. The first packet of a
+/// command tells how many packets the command spans.
+pub struct PacketReader {
+    receiving: bool,
+    previous: u8,
+    bit_index: usize,
+    packet: [u8; PACKET_LEN],
+    command: Vec<u8>,
+    remaining_packets: u8,
+    completed: Option<Vec<u8>>,
+}
+
+impl PacketReader {
+    pub fn new() -> Self {
+        PacketReader {
+            receiving: false,
+            previous: PULSE_IDLE,
+            bit_index: 0,
+            packet: [0; PACKET_LEN],
+            command: Vec::new(),
+            remaining_packets: 0,
+            completed: None,
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let lines = value & SELECT_BITMASK;
+        let previous = self.previous;
+        self.previous = lines;
+
+        if lines == previous {
+            return;
+        }
+
+        match lines {
+            PULSE_RESET => {
+                self.receiving = true;
+                self.bit_index = 0;
+                self.packet = [0; PACKET_LEN];
+            }
+            PULSE_ZERO | PULSE_ONE if self.receiving && previous == PULSE_IDLE => {
+                self.receive_bit(lines == PULSE_ONE);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns a command once all of its packets have been received.
+    pub fn take_command(&mut self) -> Option<Vec<u8>> {
+        self.completed.take()
+    }
+
+    fn receive_bit(&mut self, bit: bool) {
+        if self.bit_index == PACKET_BITS {
+            // Stop bit
+            self.receiving = false;
+            self.receive_packet();
+            return;
+        }
+
+        if bit {
+            self.packet[self.bit_index / 8] |= 1 << (self.bit_index % 8);
+        }
+        self.bit_index += 1;
+    }
+
+    fn receive_packet(&mut self) {
+        if self.remaining_packets == 0 {
+            self.command.clear();
+            self.remaining_packets = (self.packet[0] & PACKET_COUNT_BITMASK).max(1);
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        self.remaining_packets -= 1;
+
+        if self.remaining_packets == 0 {
+            self.completed = Some(std::mem::take(&mut self.command));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use super::*;
+
+    fn pulse(reader: &mut PacketReader, lines: u8) {
+        reader.write(lines);
+        reader.write(PULSE_IDLE);
+    }
+
+    fn send_bits(reader: &mut PacketReader, packet: &[u8], bits: Range<usize>) {
+        for index in bits {
+            let bit = packet[index / 8] & (1 << (index % 8)) != 0;
+            pulse(reader, if bit { PULSE_ONE } else { PULSE_ZERO });
+        }
+    }
+
+    fn send_packet(reader: &mut PacketReader, packet: &[u8]) {
+        pulse(reader, PULSE_RESET);
+        send_bits(reader, packet, 0..PACKET_BITS);
+        pulse(reader, PULSE_ZERO);
+    }
+
+    fn packet(first: u8) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        for (index, byte) in packet.iter_mut().enumerate() {
+            *byte = 0xA5 ^ index as u8;
+        }
+        packet[0] = first;
+
+        packet
+    }
+
+    #[test]
+    fn completes_packet_on_stop_bit() {
+        let mut reader = PacketReader::new();
+        let packet = packet(0x01);
+
+        pulse(&mut reader, PULSE_RESET);
+        send_bits(&mut reader, &packet, 0..PACKET_BITS);
+        assert_eq!(reader.take_command(), None);
+
+        pulse(&mut reader, PULSE_ZERO);
+        assert_eq!(reader.take_command(), Some(packet.to_vec()));
+        assert_eq!(reader.take_command(), None);
+    }
+
+    #[test]
+    fn ignores_bits_without_reset_or_idle() {
+        let mut reader = PacketReader::new();
+        let packet = packet(0x01);
+
+        // No reset pulse, nothing is received
+        send_bits(&mut reader, &packet, 0..PACKET_BITS);
+        pulse(&mut reader, PULSE_ZERO);
+        assert_eq!(reader.take_command(), None);
+
+        // A bit only counts after both lines went high again
+        pulse(&mut reader, PULSE_RESET);
+        reader.write(PULSE_ONE);
+        reader.write(PULSE_ZERO);
+        reader.write(PULSE_IDLE);
+        send_bits(&mut reader, &packet, 1..PACKET_BITS);
+        pulse(&mut reader, PULSE_ZERO);
+
+        assert_eq!(reader.take_command(), Some(packet.to_vec()));
+    }
+
+    #[test]
+    fn reset_restarts_packet() {
+        let mut reader = PacketReader::new();
+        let packet = packet(0x01);
+
+        pulse(&mut reader, PULSE_RESET);
+        send_bits(&mut reader, &[0xFF; PACKET_LEN], 0..40);
+        send_packet(&mut reader, &packet);
+
+        assert_eq!(reader.take_command(), Some(packet.to_vec()));
+    }
+
+    #[test]
+    fn joins_packets_of_a_command() {
+        let mut reader = PacketReader::new();
+        let first = packet(0x03);
+        let second = packet(0x12);
+        let third = packet(0x34);
+
+        send_packet(&mut reader, &first);
+        assert_eq!(reader.take_command(), None);
+        send_packet(&mut reader, &second);
+        assert_eq!(reader.take_command(), None);
+        send_packet(&mut reader, &third);
+
+        let command = [first, second, third].concat();
+        assert_eq!(reader.take_command(), Some(command));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::bus::FetchWrite;
+
+const SWITCH_ARMED_BITMASK: u8 = 1;
+const CURRENT_SPEED_BITMASK: u8 = 1 << 7;
+
+/// CGB KEY1 register. Arming the switch and executing STOP toggles the CPU
+/// between normal and double speed.
+pub struct SpeedSwitch {
+    double_speed: bool,
+    armed: bool,
+    cgb: bool,
+}
+
+impl SpeedSwitch {
+    pub fn new() -> Self {
+        SpeedSwitch {
+            double_speed: false,
+            armed: false,
+            cgb: false,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Performs an armed speed switch, returns whether one happened.
+    pub fn switch(&mut self) -> bool {
+        if !self.armed {
+            return false;
+        }
+
+        self.armed = false;
+        self.double_speed = !self.double_speed;
+
+        true
+    }
+}
+
+impl FetchWrite for SpeedSwitch {
+    fn fetch8(&mut self, _: u16) -> Result<u8, std::io::Error> {
+        if !self.cgb {
+            return Ok(0xFF);
+        }
+
+        let mut value = !(SWITCH_ARMED_BITMASK | CURRENT_SPEED_BITMASK);
+        if self.armed {
+            value |= SWITCH_ARMED_BITMASK;
+        }
+        if self.double_speed {
+            value |= CURRENT_SPEED_BITMASK;
+        }
+
+        Ok(value)
+    }
+
+    fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+
+    fn write8(&mut self, _: u16, value: u8) -> std::io::Result<()> {
+        if self.cgb {
+            self.armed = value & SWITCH_ARMED_BITMASK != 0;
+        }
+
+        Ok(())
+    }
+
+    fn write16(&mut self, _: u16, _: u16) -> std::io::Result<()> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY1_ADDRESS: u16 = 0xFF4D;
+
+    #[test]
+    fn switches_only_when_armed() {
+        let mut speed_switch = SpeedSwitch::new();
+        speed_switch.set_cgb_mode(true);
+        assert_eq!(speed_switch.fetch8(KEY1_ADDRESS).unwrap(), 0x7E);
+        assert!(!speed_switch.switch());
+
+        speed_switch.write8(KEY1_ADDRESS, 0x01).unwrap();
+        assert_eq!(speed_switch.fetch8(KEY1_ADDRESS).unwrap(), 0x7F);
+        assert!(speed_switch.switch());
+        assert!(speed_switch.is_double_speed());
+        assert_eq!(speed_switch.fetch8(KEY1_ADDRESS).unwrap(), 0xFE);
+
+        speed_switch.write8(KEY1_ADDRESS, 0x01).unwrap();
+        assert!(speed_switch.switch());
+        assert!(!speed_switch.is_double_speed());
+    }
+
+    #[test]
+    fn dmg_has_no_double_speed() {
+        let mut speed_switch = SpeedSwitch::new();
+        speed_switch.write8(KEY1_ADDRESS, 0x01).unwrap();
+
+        assert!(!speed_switch.switch());
+        assert_eq!(speed_switch.fetch8(KEY1_ADDRESS).unwrap(), 0xFF);
+    }
+}
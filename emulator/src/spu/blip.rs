@@ -0,0 +1,159 @@
+use std::f64::consts::PI;
+
+/// Taps of the band-limited step kernel
+const KERNEL_WIDTH: usize = 16;
+/// Fractional sample positions the kernel is precomputed for
+const KERNEL_PHASES: usize = 64;
+/// Cutoff relative to the output Nyquist frequency
+const CUTOFF: f64 = 0.9;
+/// Pole of the high-pass filter removing the DC offset, like the capacitor
+/// at the Game Boy's audio output
+const HIGH_PASS: f32 = 0.999;
+
+struct Side {
+    deltas: Vec<f32>,
+    level: f32,
+    sum: f32,
+    previous: f32,
+    output: f32,
+}
+
+impl Side {
+    fn new() -> Self {
+        Side {
+            deltas: vec![0.0; KERNEL_WIDTH],
+            level: 0.0,
+            sum: 0.0,
+            previous: 0.0,
+            output: 0.0,
+        }
+    }
+
+    fn next_sample(&mut self, index: usize) -> f32 {
+        self.sum += self.deltas[index];
+        self.output = self.sum - self.previous + HIGH_PASS * self.output;
+        self.previous = self.sum;
+        self.output
+    }
+}
+
+/// Converts the stereo step signal of the APU to a lower sample rate by
+/// band-limited synthesis: each change of level is added as a windowed
+/// sinc step at its exact position between output samples, so the output
+/// is free of aliasing.
+pub struct BlipResampler {
+    clock_rate: f64,
+    /// Output samples per input clock
+    ratio: f64,
+    /// Current time in output samples from the start of the buffer
+    position: f64,
+    left: Side,
+    right: Side,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+}
+
+impl BlipResampler {
+    pub fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        BlipResampler {
+            clock_rate: clock_rate as f64,
+            ratio: sample_rate as f64 / clock_rate as f64,
+            position: 0.0,
+            left: Side::new(),
+            right: Side::new(),
+            kernel: build_kernel(),
+        }
+    }
+
+    /// Changes the output rate without disturbing samples in flight.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.ratio = sample_rate / self.clock_rate;
+    }
+
+    /// Advances time by the given input clocks, after which the signal is
+    /// at the given level.
+    pub fn add(&mut self, clocks: u32, left: f32, right: f32) {
+        self.position += clocks as f64 * self.ratio;
+
+        let index = self.position as usize;
+        let phase = ((self.position - index as f64) * KERNEL_PHASES as f64) as usize;
+        let kernel = &self.kernel[phase.min(KERNEL_PHASES - 1)];
+
+        for (side, level) in [(&mut self.left, left), (&mut self.right, right)] {
+            let delta = level - side.level;
+            if delta == 0.0 {
+                continue;
+            }
+            side.level = level;
+
+            if side.deltas.len() < index + KERNEL_WIDTH {
+                side.deltas.resize(index + KERNEL_WIDTH, 0.0);
+            }
+            for (tap, weight) in kernel.iter().enumerate() {
+                side.deltas[index + tap] += delta * weight;
+            }
+        }
+    }
+
+    /// Number of finished output samples per side.
+    pub fn samples_available(&self) -> usize {
+        self.position as usize
+    }
+
+    /// Appends all finished samples to `output`, interleaved left/right.
+    pub fn read_samples(&mut self, output: &mut Vec<f32>) {
+        let count = self.samples_available();
+
+        for side in [&mut self.left, &mut self.right] {
+            if side.deltas.len() < count + KERNEL_WIDTH {
+                side.deltas.resize(count + KERNEL_WIDTH, 0.0);
+            }
+        }
+
+        output.reserve(count * 2);
+        for index in 0..count {
+            output.push(self.left.next_sample(index));
+            output.push(self.right.next_sample(index));
+        }
+
+        for side in [&mut self.left, &mut self.right] {
+            side.deltas.drain(..count);
+        }
+        self.position -= count as f64;
+    }
+}
+
+/// Precomputes the differentiated step, a Blackman windowed sinc, for each
+/// phase. Every phase sums up to 1 so steps keep their height.
+fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let half = KERNEL_WIDTH as f64 / 2.0;
+
+    (0..KERNEL_PHASES)
+        .map(|phase| {
+            let offset = phase as f64 / KERNEL_PHASES as f64;
+            let mut taps = [0.0f64; KERNEL_WIDTH];
+
+            for (tap, value) in taps.iter_mut().enumerate() {
+                let t = tap as f64 - half - offset + 1.0;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t * CUTOFF).sin() / (PI * t * CUTOFF)
+                };
+                let x = (t + half) / KERNEL_WIDTH as f64;
+                let window = if (0.0..=1.0).contains(&x) {
+                    0.42 - 0.5 * (2.0 * PI * x).cos() + 0.08 * (4.0 * PI * x).cos()
+                } else {
+                    0.0
+                };
+                *value = sinc * window;
+            }
+
+            let sum: f64 = taps.iter().sum();
+            let mut kernel = [0.0f32; KERNEL_WIDTH];
+            for (tap, value) in taps.iter().enumerate() {
+                kernel[tap] = (value / sum) as f32;
+            }
+            kernel
+        })
+        .collect()
+}
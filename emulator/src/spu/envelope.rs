@@ -0,0 +1,72 @@
+const INITIAL_VOLUME_SHIFT: u8 = 4;
+const ADD_MODE_BITMASK: u8 = 1 << 3;
+const PERIOD_BITMASK: u8 = 0b111;
+const DAC_ENABLE_BITMASK: u8 = 0xF8;
+
+const MAX_VOLUME: u8 = 15;
+
+/// Volume envelope controlled by NRx2.
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /// The upper five bits of NRx2 double as the DAC power switch.
+    pub fn dac_enabled(&self) -> bool {
+        self.register & DAC_ENABLE_BITMASK != 0
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    fn period(&self) -> u8 {
+        self.register & PERIOD_BITMASK
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> INITIAL_VOLUME_SHIFT;
+        self.timer = self.period();
+    }
+
+    /// Clocked by the frame sequencer at 64 Hz.
+    pub fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer > 0 {
+            return;
+        }
+
+        self.timer = self.period();
+        if self.register & ADD_MODE_BITMASK != 0 {
+            if self.volume < MAX_VOLUME {
+                self.volume += 1;
+            }
+        } else if self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
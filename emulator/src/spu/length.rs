@@ -0,0 +1,70 @@
+/// Length counter, disabling its channel after a set time when enabled.
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// Resets the counter when the APU is powered off. On DMG, the counter
+    /// itself is kept.
+    pub fn power_off(&mut self, keep_counter: bool) {
+        self.enabled = false;
+        if !keep_counter {
+            self.counter = 0;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Loads the counter from the length bits of NRx1.
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - (value as u16 & (self.max - 1));
+    }
+
+    /// Updates the enable bit of NRx4. `extra_clock` tells whether the next
+    /// frame sequencer step leaves length alone, in which case enabling the
+    /// counter clocks it once right away. Returns whether the channel has
+    /// to be disabled.
+    pub fn set_enabled(&mut self, enabled: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+
+        if !was_enabled && enabled && extra_clock && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+
+        false
+    }
+
+    pub fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    /// Clocked by the frame sequencer at 256 Hz. Returns whether the
+    /// channel has to be disabled.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
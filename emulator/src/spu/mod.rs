@@ -0,0 +1,622 @@
+use std::{io, path::Path};
+
+use crate::{bus::FetchWrite, constants::SYSCLK_FREQ};
+
+use self::{
+    blip::BlipResampler, noise::NoiseChannel, recorder::Recorder, square::SquareChannel,
+    vgm::VgmLogger, wave::WaveChannel,
+};
+
+pub use self::{
+    scope::{Scope, SCOPE_LEN},
+    sink::{BufferSink, NullSink},
+};
+
+mod blip;
+mod envelope;
+mod length;
+mod noise;
+mod recorder;
+mod scope;
+mod sink;
+mod square;
+mod vgm;
+mod wave;
+
+const NR10_ADDRESS: u16 = 0xFF10;
+const NR14_ADDRESS: u16 = 0xFF14;
+const NR20_ADDRESS: u16 = 0xFF15;
+const NR24_ADDRESS: u16 = 0xFF19;
+const NR30_ADDRESS: u16 = 0xFF1A;
+const NR34_ADDRESS: u16 = 0xFF1E;
+const NR40_ADDRESS: u16 = 0xFF1F;
+const NR44_ADDRESS: u16 = 0xFF23;
+const NR50_ADDRESS: u16 = 0xFF24;
+const NR51_ADDRESS: u16 = 0xFF25;
+const NR52_ADDRESS: u16 = 0xFF26;
+const WAVE_RAM_START_ADDRESS: u16 = 0xFF30;
+const WAVE_RAM_END_ADDRESS: u16 = 0xFF3F;
+
+const NR11_ADDRESS: u16 = 0xFF11;
+const NR21_ADDRESS: u16 = 0xFF16;
+const NR31_ADDRESS: u16 = 0xFF1B;
+const NR41_ADDRESS: u16 = 0xFF20;
+
+const POWER_BITMASK: u8 = 1 << 7;
+const NR52_UNUSED_BITMASK: u8 = 0x70;
+
+/// DIV bit whose falling edge clocks the frame sequencer, at normal and at
+/// double speed.
+const FRAME_SEQUENCER_DIV_BITMASK: u8 = 1 << 4;
+const FRAME_SEQUENCER_DIV_DOUBLE_SPEED_BITMASK: u8 = 1 << 5;
+
+pub const CHANNELS: usize = 4;
+
+/// Sample frames collected before they are pushed to the sink
+const AUDIO_BLOCK_FRAMES: usize = 512;
+
+/// Receives the sound output, interleaved left and right samples between
+/// -1 and 1 at the sample rate the sink was set up with.
+pub trait AudioSink {
+    fn push_samples(&mut self, samples: &[f32]);
+}
+
+pub struct Spu {
+    power: bool,
+    cgb: bool,
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    nr50: u8,
+    nr51: u8,
+    /// Next step of the 512 Hz frame sequencer
+    frame_step: u8,
+    div_bit: bool,
+    /// Clock cycles left over from the last call to `next`, less than one
+    /// M-cycle
+    leftover_cycles: u8,
+    sink: Box<dyn AudioSink>,
+    resampler: Option<BlipResampler>,
+    samples: Vec<f32>,
+    recorder: Option<Recorder>,
+    vgm: Option<VgmLogger>,
+    muted: [bool; CHANNELS],
+    solo: Option<usize>,
+    scope: Scope,
+}
+
+impl Default for Spu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Spu {
+    pub fn new() -> Self {
+        Spu {
+            power: false,
+            cgb: false,
+            square1: SquareChannel::new(true),
+            square2: SquareChannel::new(false),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            nr50: 0,
+            nr51: 0,
+            frame_step: 0,
+            div_bit: false,
+            leftover_cycles: 0,
+            sink: Box::new(NullSink),
+            resampler: None,
+            samples: Vec::new(),
+            recorder: None,
+            vgm: None,
+            muted: [false; CHANNELS],
+            solo: None,
+            scope: Scope::new(),
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    /// Sends the output, resampled to `sample_rate`, to `sink`.
+    pub fn set_sink(&mut self, sink: Box<dyn AudioSink>, sample_rate: u32) {
+        self.sink = sink;
+        self.resampler = Some(BlipResampler::new(SYSCLK_FREQ as u32, sample_rate));
+    }
+
+    /// Puts the APU back into its power on state. The sink, recordings and
+    /// the mute and solo settings are kept.
+    pub fn reset(&mut self) {
+        self.flush_samples();
+
+        *self = Spu {
+            cgb: self.cgb,
+            sink: std::mem::replace(&mut self.sink, Box::new(NullSink)),
+            resampler: self.resampler.take(),
+            recorder: self.recorder.take(),
+            vgm: self.vgm.take(),
+            muted: self.muted,
+            solo: self.solo,
+            ..Spu::new()
+        };
+    }
+
+    /// Fine-tunes the rate the output is resampled to, e.g. to keep an
+    /// audio queue from running dry or overflowing.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.set_sample_rate(sample_rate);
+        }
+    }
+
+    /// Pushes all samples produced so far to the sink, even if they don't
+    /// fill a block.
+    pub fn flush_samples(&mut self) {
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.read_samples(&mut self.samples);
+        }
+
+        if !self.samples.is_empty() {
+            self.sink.push_samples(&self.samples);
+            self.samples.clear();
+        }
+    }
+
+    /// Starts writing the output to a WAV file at `path`, and with `stems`
+    /// each channel to a WAV file of its own. The output is recorded as
+    /// heard, without muted channels, the stems are recorded in full.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P, stems: bool) -> io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::new(path, stems)?);
+
+        Ok(())
+    }
+
+    /// Finishes the current recording, if any.
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Starts logging register writes to a VGM file at `path`. Start it
+    /// before the game sets up sound, earlier writes are not in the log.
+    pub fn start_vgm_log<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        loop_detection: bool,
+    ) -> io::Result<()> {
+        self.stop_vgm_log()?;
+        self.vgm = Some(VgmLogger::new(path, loop_detection)?);
+
+        Ok(())
+    }
+
+    /// Writes the current VGM log, if any.
+    pub fn stop_vgm_log(&mut self) -> io::Result<()> {
+        match self.vgm.take() {
+            Some(vgm) => vgm.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.muted[channel] = muted;
+    }
+
+    pub fn is_channel_muted(&self, channel: usize) -> bool {
+        self.muted[channel]
+    }
+
+    /// Plays only the given channel, mutes included, or all channels that
+    /// are not muted.
+    pub fn set_solo(&mut self, channel: Option<usize>) {
+        self.solo = channel;
+    }
+
+    pub fn solo(&self) -> Option<usize> {
+        self.solo
+    }
+
+    /// Whether the channel is in the mix, as far as mute and solo go.
+    pub fn is_channel_audible(&self, channel: usize) -> bool {
+        match self.solo {
+            Some(solo) => channel == solo,
+            None => !self.muted[channel],
+        }
+    }
+
+    /// Recent output of each channel, independent of mute and solo.
+    pub fn scope(&self) -> &Scope {
+        &self.scope
+    }
+
+    /// Advances the channels by the given clock cycles at normal speed.
+    /// The frame sequencer follows the falling edges of a DIV bit.
+    pub fn next(&mut self, cycles: u8, div: u8, double_speed: bool) {
+        let div_bitmask = if double_speed {
+            FRAME_SEQUENCER_DIV_DOUBLE_SPEED_BITMASK
+        } else {
+            FRAME_SEQUENCER_DIV_BITMASK
+        };
+        if let Some(vgm) = self.vgm.as_mut() {
+            vgm.next(cycles as u32);
+        }
+
+        let div_bit = div & div_bitmask != 0;
+        let falling_edge = self.div_bit && !div_bit;
+        self.div_bit = div_bit;
+
+        if self.power && falling_edge {
+            self.step_frame_sequencer();
+        }
+
+        // Step in M-cycles so the resampler sees every change of the output.
+        // In double speed the bus runs us 2 cycles at a time.
+        let cycles = self.leftover_cycles as u16 + cycles as u16;
+        self.leftover_cycles = (cycles % 4) as u8;
+
+        let mut resampler = self.resampler.take();
+        let mut recorder = self.recorder.take();
+        for _ in 0..cycles / 4 {
+            if self.power {
+                self.square1.next(4);
+                self.square2.next(4);
+                self.wave.next(4);
+                self.noise.next(4);
+            }
+
+            if self.scope.next(4) {
+                let mut outputs = [0.0; CHANNELS];
+                for (channel, output) in outputs.iter_mut().enumerate() {
+                    *output = self.channel_output(channel);
+                }
+                self.scope.push(outputs);
+            }
+
+            if let Some(resampler) = resampler.as_mut() {
+                let (left, right) = self.output();
+                resampler.add(4, left, right);
+            }
+
+            if let Some(recorder) = recorder.as_mut() {
+                let mut channels = [0.0; CHANNELS];
+                if recorder.records_stems() {
+                    for (channel, output) in channels.iter_mut().enumerate() {
+                        *output = self.channel_output(channel);
+                    }
+                }
+                recorder.add(4, self.output(), &channels);
+            }
+        }
+        self.resampler = resampler;
+
+        if let Some(recorder) = recorder.as_mut() {
+            if recorder.samples_available() >= AUDIO_BLOCK_FRAMES {
+                recorder.write();
+            }
+        }
+        self.recorder = recorder;
+
+        if let Some(resampler) = self.resampler.as_mut() {
+            if resampler.samples_available() >= AUDIO_BLOCK_FRAMES {
+                self.flush_samples();
+            }
+        }
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        match self.frame_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.square1.clock_sweep();
+            }
+            7 => {
+                self.square1.clock_envelope();
+                self.square2.clock_envelope();
+                self.noise.clock_envelope();
+            }
+            _ => {}
+        }
+
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.square1.clock_length();
+        self.square2.clock_length();
+        self.wave.clock_length();
+        self.noise.clock_length();
+    }
+
+    /// Whether the next frame sequencer step leaves the length counters
+    /// alone.
+    fn extra_length_clock(&self) -> bool {
+        self.frame_step % 2 == 1
+    }
+
+    /// Output of a channel's DAC between -1 and 1, 0 when the DAC is off.
+    pub fn channel_output(&self, channel: usize) -> f32 {
+        let (dac_enabled, output) = match channel {
+            0 => (self.square1.dac_enabled(), self.square1.output()),
+            1 => (self.square2.dac_enabled(), self.square2.output()),
+            2 => (self.wave.dac_enabled(), self.wave.output()),
+            3 => (self.noise.dac_enabled(), self.noise.output()),
+            _ => panic!("No such sound channel: {}", channel),
+        };
+
+        if !self.power || !dac_enabled {
+            return 0.0;
+        }
+
+        1.0 - output as f32 / 7.5
+    }
+
+    /// Mixes the channels as selected by NR51 and scales them by the
+    /// master volume in NR50. Returns left and right between -1 and 1.
+    /// Muted channels are left out.
+    pub fn output(&self) -> (f32, f32) {
+        let mut left = 0.0;
+        let mut right = 0.0;
+
+        for channel in (0..CHANNELS).filter(|&channel| self.is_channel_audible(channel)) {
+            let output = self.channel_output(channel);
+            if self.nr51 & (1 << (channel + 4)) != 0 {
+                left += output;
+            }
+            if self.nr51 & (1 << channel) != 0 {
+                right += output;
+            }
+        }
+
+        let left_volume = ((self.nr50 >> 4) & 0b111) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0b111) as f32 + 1.0;
+
+        (
+            left * left_volume / 8.0 / CHANNELS as f32,
+            right * right_volume / 8.0 / CHANNELS as f32,
+        )
+    }
+
+    fn read_nr52(&self) -> u8 {
+        let power = if self.power { POWER_BITMASK } else { 0 };
+        let channels = [
+            self.square1.is_enabled(),
+            self.square2.is_enabled(),
+            self.wave.is_enabled(),
+            self.noise.is_enabled(),
+        ];
+
+        channels
+            .iter()
+            .enumerate()
+            .filter(|(_, enabled)| **enabled)
+            .fold(power | NR52_UNUSED_BITMASK, |value, (channel, _)| {
+                value | (1 << channel)
+            })
+    }
+
+    fn write_nr52(&mut self, value: u8) {
+        let power = value & POWER_BITMASK != 0;
+
+        if self.power && !power {
+            // Powering off clears all registers. DMG keeps the length
+            // counters.
+            let keep_length = !self.cgb;
+            self.square1.power_off(keep_length);
+            self.square2.power_off(keep_length);
+            self.wave.power_off(keep_length);
+            self.noise.power_off(keep_length);
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.power && power {
+            self.frame_step = 0;
+        }
+
+        self.power = power;
+    }
+}
+
+impl FetchWrite for Spu {
+    fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        let value = match address {
+            NR10_ADDRESS..=NR14_ADDRESS => self.square1.read((address - NR10_ADDRESS) as u8),
+            NR20_ADDRESS..=NR24_ADDRESS => self.square2.read((address - NR20_ADDRESS) as u8),
+            NR30_ADDRESS..=NR34_ADDRESS => self.wave.read((address - NR30_ADDRESS) as u8),
+            NR40_ADDRESS..=NR44_ADDRESS => self.noise.read((address - NR40_ADDRESS) as u8),
+            NR50_ADDRESS => self.nr50,
+            NR51_ADDRESS => self.nr51,
+            NR52_ADDRESS => self.read_nr52(),
+            WAVE_RAM_START_ADDRESS..=WAVE_RAM_END_ADDRESS => {
+                self.wave.read_ram((address - WAVE_RAM_START_ADDRESS) as u8)
+            }
+            _ => 0xFF,
+        };
+
+        Ok(value)
+    }
+
+    fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+
+    fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        if let Some(vgm) = self.vgm.as_mut() {
+            vgm.write(address, value);
+        }
+
+        match address {
+            NR52_ADDRESS => {
+                self.write_nr52(value);
+                return Ok(());
+            }
+            WAVE_RAM_START_ADDRESS..=WAVE_RAM_END_ADDRESS => {
+                self.wave
+                    .write_ram((address - WAVE_RAM_START_ADDRESS) as u8, value);
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if !self.power {
+            // Only the length counters of DMG can be written while off
+            if !self.cgb {
+                match address {
+                    NR11_ADDRESS => self.square1.write_length(value),
+                    NR21_ADDRESS => self.square2.write_length(value),
+                    NR31_ADDRESS => self.wave.write_length(value),
+                    NR41_ADDRESS => self.noise.write_length(value),
+                    _ => {}
+                }
+            }
+            return Ok(());
+        }
+
+        let extra_length_clock = self.extra_length_clock();
+        match address {
+            NR10_ADDRESS..=NR14_ADDRESS => {
+                self.square1
+                    .write((address - NR10_ADDRESS) as u8, value, extra_length_clock)
+            }
+            NR20_ADDRESS..=NR24_ADDRESS => {
+                self.square2
+                    .write((address - NR20_ADDRESS) as u8, value, extra_length_clock)
+            }
+            NR30_ADDRESS..=NR34_ADDRESS => {
+                self.wave
+                    .write((address - NR30_ADDRESS) as u8, value, extra_length_clock)
+            }
+            NR40_ADDRESS..=NR44_ADDRESS => {
+                self.noise
+                    .write((address - NR40_ADDRESS) as u8, value, extra_length_clock)
+            }
+            NR50_ADDRESS => self.nr50 = value,
+            NR51_ADDRESS => self.nr51 = value,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn write16(&mut self, _: u16, _: u16) -> std::io::Result<()> {
+        panic!("16 bit operations not supported with 8 bit register")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+    const NR12_ADDRESS: u16 = 0xFF12;
+    const NR13_ADDRESS: u16 = 0xFF13;
+
+    fn spu_with_buffer() -> (Spu, Rc<RefCell<Vec<f32>>>) {
+        let sink = BufferSink::new();
+        let buffer = sink.buffer();
+        let mut spu = Spu::new();
+        spu.set_sink(Box::new(sink), SAMPLE_RATE);
+
+        (spu, buffer)
+    }
+
+    /// Runs the APU for a tenth of a second, returns the stereo frames
+    /// produced.
+    fn run(spu: &mut Spu, buffer: &Rc<RefCell<Vec<f32>>>) -> Vec<f32> {
+        let mut cycles: i64 = 0;
+        while cycles < SYSCLK_FREQ / 10 {
+            spu.next(4, (cycles >> 8) as u8, false);
+            cycles += 4;
+        }
+        spu.flush_samples();
+
+        buffer.take()
+    }
+
+    /// Same as `run`, but the way the bus drives the APU in double speed:
+    /// 2 cycles at a time with DIV running twice as fast.
+    fn run_double_speed(spu: &mut Spu, buffer: &Rc<RefCell<Vec<f32>>>) -> Vec<f32> {
+        let mut cycles: i64 = 0;
+        while cycles < SYSCLK_FREQ / 10 {
+            spu.next(2, (cycles >> 7) as u8, true);
+            cycles += 2;
+        }
+        spu.flush_samples();
+
+        buffer.take()
+    }
+
+    fn play_square(spu: &mut Spu) {
+        spu.write8(NR52_ADDRESS, 0x80).unwrap();
+        spu.write8(NR50_ADDRESS, 0x77).unwrap();
+        spu.write8(NR51_ADDRESS, 0xFF).unwrap();
+
+        // 50% duty, full volume, about 1 kHz
+        spu.write8(NR11_ADDRESS, 0x80).unwrap();
+        spu.write8(NR12_ADDRESS, 0xF0).unwrap();
+        spu.write8(NR13_ADDRESS, 0x7D).unwrap();
+        spu.write8(NR14_ADDRESS, 0x87).unwrap();
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+    }
+
+    fn assert_tenth_of_a_second(samples: &[f32]) {
+        let frames = samples.len() as i64 / 2;
+        assert!(
+            (frames - SAMPLE_RATE as i64 / 10).abs() <= 2,
+            "{} frames",
+            frames
+        );
+    }
+
+    #[test]
+    fn silent_when_powered_off() {
+        let (mut spu, buffer) = spu_with_buffer();
+        spu.write8(NR52_ADDRESS, 0).unwrap();
+
+        let samples = run(&mut spu, &buffer);
+
+        assert_tenth_of_a_second(&samples);
+        assert!(samples.iter().all(|sample| sample.abs() < 1e-3));
+    }
+
+    #[test]
+    fn square_channel_plays() {
+        let (mut spu, buffer) = spu_with_buffer();
+        play_square(&mut spu);
+
+        let samples = run(&mut spu, &buffer);
+
+        assert_tenth_of_a_second(&samples);
+        let peak = peak(&samples);
+        assert!(peak > 0.1, "peak {}", peak);
+        assert!(peak <= 1.0, "peak {}", peak);
+    }
+
+    #[test]
+    fn plays_in_double_speed() {
+        let (mut spu, buffer) = spu_with_buffer();
+        play_square(&mut spu);
+
+        let samples = run_double_speed(&mut spu, &buffer);
+
+        assert_tenth_of_a_second(&samples);
+        let peak = peak(&samples);
+        assert!(peak > 0.1, "peak {}", peak);
+    }
+}
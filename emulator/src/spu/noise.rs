@@ -0,0 +1,157 @@
+use super::{envelope::Envelope, length::LengthCounter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+const CLOCK_SHIFT_SHIFT: u8 = 4;
+const WIDTH_MODE_BITMASK: u8 = 1 << 3;
+const DIVISOR_BITMASK: u8 = 0b111;
+const LENGTH_BITMASK: u8 = 0x3F;
+const LENGTH_ENABLE_BITMASK: u8 = 1 << 6;
+const TRIGGER_BITMASK: u8 = 1 << 7;
+
+const NOISE_LENGTH: u16 = 64;
+const LFSR_SEED: u16 = 0x7FFF;
+/// Clock shifts of 14 and 15 stop the LFSR
+const MAX_CLOCK_SHIFT: u8 = 13;
+
+/// Noise channel driven by a linear feedback shift register, which is 15
+/// bits wide or 7 bits in width mode.
+pub struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    register: u8,
+    timer: u32,
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(NOISE_LENGTH),
+            envelope: Envelope::new(),
+            register: 0,
+            timer: DIVISORS[0],
+            lfsr: LFSR_SEED,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    /// Digital output between 0 and 15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        (!self.lfsr & 1) as u8 * self.envelope.volume()
+    }
+
+    fn clock_shift(&self) -> u8 {
+        self.register >> CLOCK_SHIFT_SHIFT
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[(self.register & DIVISOR_BITMASK) as usize] << self.clock_shift()
+    }
+
+    pub fn next(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            if self.clock_shift() <= MAX_CLOCK_SHIFT {
+                self.step_lfsr();
+            }
+        }
+
+        self.timer -= cycles;
+    }
+
+    fn step_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+
+        if self.register & WIDTH_MODE_BITMASK != 0 {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Reads NR41-NR44, with write-only bits reading as 1.
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            2 => self.envelope.read(),
+            3 => self.register,
+            4 => {
+                let length_enabled = if self.length.is_enabled() {
+                    LENGTH_ENABLE_BITMASK
+                } else {
+                    0
+                };
+                length_enabled | !LENGTH_ENABLE_BITMASK
+            }
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes NR41-NR44. `extra_length_clock` tells whether the next frame
+    /// sequencer step skips the length counters.
+    pub fn write(&mut self, register: u8, value: u8, extra_length_clock: bool) {
+        match register {
+            1 => self.length.load(value & LENGTH_BITMASK),
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.register = value,
+            4 => {
+                let length_enabled = value & LENGTH_ENABLE_BITMASK != 0;
+                if self.length.set_enabled(length_enabled, extra_length_clock) {
+                    self.enabled = false;
+                }
+
+                if value & TRIGGER_BITMASK != 0 {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.length.trigger(extra_length_clock);
+                    self.envelope.trigger();
+                    self.timer = self.period();
+                    self.lfsr = LFSR_SEED;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = std::mem::replace(&mut self.length, LengthCounter::new(NOISE_LENGTH));
+        length.power_off(keep_length);
+
+        *self = NoiseChannel::new();
+        self.length = length;
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & LENGTH_BITMASK);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use super::{blip::BlipResampler, CHANNELS};
+use crate::constants::SYSCLK_FREQ;
+
+const RECORDING_SAMPLE_RATE: u32 = 48000;
+
+/// File name suffixes of the per-channel stems
+const STEM_NAMES: [&str; CHANNELS] = ["square1", "square2", "wave", "noise"];
+
+fn to_io_error(error: hound::Error) -> io::Error {
+    match error {
+        hound::Error::IoError(error) => error,
+        error => io::Error::other(error.to_string()),
+    }
+}
+
+struct Track {
+    writer: WavWriter<BufWriter<File>>,
+    resampler: BlipResampler,
+    stereo: bool,
+    samples: Vec<f32>,
+}
+
+impl Track {
+    fn create(path: &Path, stereo: bool) -> io::Result<Self> {
+        let spec = WavSpec {
+            channels: if stereo { 2 } else { 1 },
+            sample_rate: RECORDING_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+
+        Ok(Track {
+            writer: WavWriter::create(path, spec).map_err(to_io_error)?,
+            resampler: BlipResampler::new(SYSCLK_FREQ as u32, RECORDING_SAMPLE_RATE),
+            stereo,
+            samples: Vec::new(),
+        })
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        self.resampler.read_samples(&mut self.samples);
+
+        // Mono tracks only use the left side of the resampler
+        let step = if self.stereo { 1 } else { 2 };
+        for sample in self.samples.iter().step_by(step) {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_sample(sample).map_err(to_io_error)?;
+        }
+        self.samples.clear();
+
+        Ok(())
+    }
+}
+
+/// Writes the mixed output to a stereo WAV file and optionally each
+/// channel, before mixing and panning, to a mono WAV file next to it.
+/// Stems keep their share of the mix so they add up without clipping.
+pub struct Recorder {
+    mixed: Track,
+    stems: Vec<Track>,
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    /// Stems are named after the recording, e.g. `song-square1.wav` for
+    /// `song.wav`.
+    pub fn new<P: AsRef<Path>>(path: P, stems: bool) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mixed = Track::create(path, true)?;
+
+        let stems = if stems {
+            STEM_NAMES
+                .iter()
+                .map(|name| Track::create(&stem_path(path, name), false))
+                .collect::<io::Result<Vec<Track>>>()?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Recorder {
+            mixed,
+            stems,
+            error: None,
+        })
+    }
+
+    pub fn records_stems(&self) -> bool {
+        !self.stems.is_empty()
+    }
+
+    /// Advances time by the given clocks, after which the output is at the
+    /// given levels.
+    pub fn add(&mut self, clocks: u32, (left, right): (f32, f32), channels: &[f32; CHANNELS]) {
+        self.mixed.resampler.add(clocks, left, right);
+        for (stem, &output) in self.stems.iter_mut().zip(channels) {
+            stem.resampler.add(clocks, output / CHANNELS as f32, 0.0);
+        }
+    }
+
+    /// Number of finished sample frames not written yet.
+    pub fn samples_available(&self) -> usize {
+        self.mixed.resampler.samples_available()
+    }
+
+    /// Writes the samples finished so far. The first error stops the
+    /// recording and is returned by `finish`.
+    pub fn write(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = std::iter::once(&mut self.mixed)
+            .chain(self.stems.iter_mut())
+            .try_for_each(Track::write);
+        self.error = result.err();
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write();
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        for track in std::iter::once(self.mixed).chain(self.stems) {
+            track.writer.finalize().map_err(to_io_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn stem_path(path: &Path, name: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}.wav", stem, name))
+}
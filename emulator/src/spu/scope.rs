@@ -0,0 +1,57 @@
+use super::CHANNELS;
+
+/// Samples kept per channel
+pub const SCOPE_LEN: usize = 1024;
+/// Clock cycles between two samples, about 32 ms of sound in total
+const SCOPE_INTERVAL: u32 = 128;
+
+/// Ring buffers of the recent output of each channel, for oscilloscope
+/// views.
+pub struct Scope {
+    samples: [[f32; SCOPE_LEN]; CHANNELS],
+    position: usize,
+    cycles: u32,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Scope {
+            samples: [[0.0; SCOPE_LEN]; CHANNELS],
+            position: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Whether `cycles` more clock cycles make a new sample due.
+    pub(super) fn next(&mut self, cycles: u32) -> bool {
+        self.cycles += cycles;
+        if self.cycles < SCOPE_INTERVAL {
+            return false;
+        }
+
+        self.cycles -= SCOPE_INTERVAL;
+        true
+    }
+
+    pub(super) fn push(&mut self, outputs: [f32; CHANNELS]) {
+        for (samples, output) in self.samples.iter_mut().zip(outputs) {
+            samples[self.position] = output;
+        }
+        self.position = (self.position + 1) % SCOPE_LEN;
+    }
+
+    /// Recent output of a channel between -1 and 1, oldest first.
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        let samples = &self.samples[channel];
+        samples[self.position..]
+            .iter()
+            .chain(&samples[..self.position])
+            .copied()
+    }
+}
@@ -0,0 +1,34 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::AudioSink;
+
+/// Discards all samples.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn push_samples(&mut self, _: &[f32]) {}
+}
+
+/// Collects samples in memory, e.g. to check APU output in tests. The
+/// buffer stays reachable through `buffer()` after the sink is handed to
+/// the APU.
+#[derive(Default)]
+pub struct BufferSink {
+    buffer: Rc<RefCell<Vec<f32>>>,
+}
+
+impl BufferSink {
+    pub fn new() -> Self {
+        BufferSink::default()
+    }
+
+    pub fn buffer(&self) -> Rc<RefCell<Vec<f32>>> {
+        self.buffer.clone()
+    }
+}
+
+impl AudioSink for BufferSink {
+    fn push_samples(&mut self, samples: &[f32]) {
+        self.buffer.borrow_mut().extend_from_slice(samples);
+    }
+}
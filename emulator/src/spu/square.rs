@@ -0,0 +1,271 @@
+use super::{envelope::Envelope, length::LengthCounter};
+
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+const SWEEP_PERIOD_SHIFT: u8 = 4;
+const SWEEP_NEGATE_BITMASK: u8 = 1 << 3;
+const SWEEP_SHIFT_BITMASK: u8 = 0b111;
+const MAX_FREQUENCY: u16 = 0x7FF;
+
+const DUTY_SHIFT: u8 = 6;
+const LENGTH_BITMASK: u8 = 0x3F;
+const LENGTH_ENABLE_BITMASK: u8 = 1 << 6;
+const TRIGGER_BITMASK: u8 = 1 << 7;
+const FREQUENCY_HIGH_BITMASK: u8 = 0b111;
+
+const SQUARE_LENGTH: u16 = 64;
+
+/// Frequency sweep of square channel 1, controlled by NR10.
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    timer: u8,
+    shadow: u16,
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep {
+            register: 0,
+            enabled: false,
+            timer: 0,
+            shadow: 0,
+            negate_used: false,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        (self.register >> SWEEP_PERIOD_SHIFT) & 0b111
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & SWEEP_SHIFT_BITMASK
+    }
+
+    fn negate(&self) -> bool {
+        self.register & SWEEP_NEGATE_BITMASK != 0
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 is treated as 8
+        self.timer = match self.period() {
+            0 => 8,
+            period => period,
+        };
+    }
+
+    /// Returns the next frequency, or `None` when it overflows.
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.shift();
+        let frequency = if self.negate() {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+
+        (frequency <= MAX_FREQUENCY).then_some(frequency)
+    }
+}
+
+/// Square wave channel. Channel 1 adds a frequency sweep to channel 2.
+pub struct SquareChannel {
+    enabled: bool,
+    sweep: Option<Sweep>,
+    duty: u8,
+    duty_step: u8,
+    length: LengthCounter,
+    envelope: Envelope,
+    frequency: u16,
+    timer: u32,
+}
+
+impl SquareChannel {
+    pub fn new(with_sweep: bool) -> Self {
+        SquareChannel {
+            enabled: false,
+            sweep: with_sweep.then(Sweep::new),
+            duty: 0,
+            duty_step: 0,
+            length: LengthCounter::new(SQUARE_LENGTH),
+            envelope: Envelope::new(),
+            frequency: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    /// Digital output between 0 and 15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step)) & 1;
+        high * self.envelope.volume()
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    pub fn next(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer > 0 {
+            return;
+        }
+
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+
+        match sweep.calculate() {
+            Some(frequency) if sweep.shift() != 0 => {
+                sweep.shadow = frequency;
+                self.frequency = frequency;
+
+                // The new frequency is checked for overflow right away
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+
+    /// Reads NRx0-NRx4, with write-only bits reading as 1.
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0 => match self.sweep.as_ref() {
+                Some(sweep) => sweep.register | 0x80,
+                None => 0xFF,
+            },
+            1 => (self.duty << DUTY_SHIFT) | LENGTH_BITMASK,
+            2 => self.envelope.read(),
+            3 => 0xFF,
+            4 => {
+                let length_enabled = if self.length.is_enabled() {
+                    LENGTH_ENABLE_BITMASK
+                } else {
+                    0
+                };
+                length_enabled | !LENGTH_ENABLE_BITMASK
+            }
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes NRx0-NRx4. `extra_length_clock` tells whether the next frame
+    /// sequencer step skips the length counters.
+    pub fn write(&mut self, register: u8, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    let negate_cleared = sweep.negate() && value & SWEEP_NEGATE_BITMASK == 0;
+                    sweep.register = value & 0x7F;
+
+                    // Leaving negate mode after using it disables the channel
+                    if negate_cleared && sweep.negate_used {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = value >> DUTY_SHIFT;
+                self.length.load(value & LENGTH_BITMASK);
+            }
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency =
+                    (self.frequency & 0xFF) | (((value & FREQUENCY_HIGH_BITMASK) as u16) << 8);
+
+                let length_enabled = value & LENGTH_ENABLE_BITMASK != 0;
+                if self.length.set_enabled(length_enabled, extra_length_clock) {
+                    self.enabled = false;
+                }
+
+                if value & TRIGGER_BITMASK != 0 {
+                    self.trigger(extra_length_clock);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets only the length counter, which stays writable on DMG while the
+    /// APU is powered off.
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & LENGTH_BITMASK);
+    }
+
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = std::mem::replace(&mut self.length, LengthCounter::new(SQUARE_LENGTH));
+        length.power_off(keep_length);
+
+        *self = SquareChannel::new(self.sweep.is_some());
+        self.length = length;
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(extra_length_clock);
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        let frequency = self.frequency;
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = frequency;
+            sweep.negate_used = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+
+            if sweep.shift() != 0 && sweep.calculate().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::constants::SYSCLK_FREQ;
+
+/// VGM timestamps count samples at 44.1 kHz
+const VGM_SAMPLE_RATE: u64 = 44100;
+const VGM_VERSION: u32 = 0x161;
+const VGM_HEADER_LEN: usize = 0x100;
+
+const VGM_EOF_OFFSET: usize = 0x04;
+const VGM_VERSION_OFFSET: usize = 0x08;
+const VGM_TOTAL_SAMPLES_OFFSET: usize = 0x18;
+const VGM_LOOP_OFFSET: usize = 0x1C;
+const VGM_LOOP_SAMPLES_OFFSET: usize = 0x20;
+const VGM_DATA_OFFSET: usize = 0x34;
+const VGM_DMG_CLOCK_OFFSET: usize = 0x80;
+
+const VGM_DMG_WRITE: u8 = 0xB3;
+const VGM_WAIT: u8 = 0x61;
+const VGM_WAIT_SHORT: u8 = 0x70;
+const VGM_END: u8 = 0x66;
+
+const APU_REGISTER_START_ADDRESS: u16 = 0xFF10;
+
+/// Shortest loop, in register writes, loop detection accepts
+const MIN_LOOP_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct VgmWrite {
+    /// Samples since the previous write
+    wait: u32,
+    register: u8,
+    value: u8,
+}
+
+/// Logs APU register writes and saves them as a VGM file with Game Boy
+/// DMG commands.
+pub struct VgmLogger {
+    file: BufWriter<File>,
+    loop_detection: bool,
+    writes: Vec<VgmWrite>,
+    cycles: u64,
+    last_sample: u64,
+}
+
+impl VgmLogger {
+    /// Creates the file right away, it is written by `finish`. With
+    /// `loop_detection` the log is cut after the first repetition of a
+    /// repeating tail, which then loops.
+    pub fn new<P: AsRef<Path>>(path: P, loop_detection: bool) -> io::Result<Self> {
+        Ok(VgmLogger {
+            file: BufWriter::new(File::create(path)?),
+            loop_detection,
+            writes: Vec::new(),
+            cycles: 0,
+            last_sample: 0,
+        })
+    }
+
+    pub fn next(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+    }
+
+    fn take_wait(&mut self) -> u32 {
+        let sample = self.cycles * VGM_SAMPLE_RATE / SYSCLK_FREQ as u64;
+        let wait = sample - self.last_sample;
+        self.last_sample = sample;
+        wait as u32
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let wait = self.take_wait();
+        self.writes.push(VgmWrite {
+            wait,
+            register: (address - APU_REGISTER_START_ADDRESS) as u8,
+            value,
+        });
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        let end_wait = self.take_wait();
+
+        // Loop over `writes[start..end]`, waiting `end_wait` before jumping
+        // back
+        let (writes, end_wait, loop_start) = match self.find_loop() {
+            Some((start, len)) => {
+                let end = start + len;
+                (&self.writes[..end], self.writes[end].wait, Some(start))
+            }
+            None => (&self.writes[..], end_wait, None),
+        };
+
+        let mut data = Vec::new();
+        let mut loop_offset = None;
+        for (index, write) in writes.iter().enumerate() {
+            push_wait(&mut data, write.wait);
+            if loop_start == Some(index) {
+                loop_offset = Some(data.len());
+            }
+            data.extend_from_slice(&[VGM_DMG_WRITE, write.register, write.value]);
+        }
+        push_wait(&mut data, end_wait);
+        data.push(VGM_END);
+
+        let total_samples: u32 = writes.iter().map(|write| write.wait).sum::<u32>() + end_wait;
+        let loop_samples = match loop_start {
+            Some(start) => {
+                writes[start + 1..]
+                    .iter()
+                    .map(|write| write.wait)
+                    .sum::<u32>()
+                    + end_wait
+            }
+            None => 0,
+        };
+
+        let mut header = [0u8; VGM_HEADER_LEN];
+        header[..4].copy_from_slice(b"Vgm ");
+        let mut set = |offset: usize, value: u32| {
+            header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        set(
+            VGM_EOF_OFFSET,
+            (VGM_HEADER_LEN + data.len() - VGM_EOF_OFFSET) as u32,
+        );
+        set(VGM_VERSION_OFFSET, VGM_VERSION);
+        set(VGM_TOTAL_SAMPLES_OFFSET, total_samples);
+        if let Some(offset) = loop_offset {
+            set(
+                VGM_LOOP_OFFSET,
+                (VGM_HEADER_LEN + offset - VGM_LOOP_OFFSET) as u32,
+            );
+            set(VGM_LOOP_SAMPLES_OFFSET, loop_samples);
+        }
+        set(VGM_DATA_OFFSET, (VGM_HEADER_LEN - VGM_DATA_OFFSET) as u32);
+        set(VGM_DMG_CLOCK_OFFSET, SYSCLK_FREQ as u32);
+
+        self.file.write_all(&header)?;
+        self.file.write_all(&data)?;
+        self.file.flush()
+    }
+
+    /// Finds the shortest period the tail of the log repeats with, at least
+    /// twice, and the earliest write the repetition goes back to. Returns
+    /// its start and length.
+    fn find_loop(&self) -> Option<(usize, usize)> {
+        if !self.loop_detection {
+            return None;
+        }
+
+        let reversed: Vec<VgmWrite> = self.writes.iter().rev().copied().collect();
+        let matches = z_function(&reversed);
+
+        (MIN_LOOP_LEN..=reversed.len() / 2)
+            .find(|&len| matches[len] >= len)
+            .map(|len| (reversed.len() - len - matches[len], len))
+    }
+}
+
+fn push_wait(data: &mut Vec<u8>, mut wait: u32) {
+    while wait > 0 {
+        if wait <= 16 {
+            data.push(VGM_WAIT_SHORT + wait as u8 - 1);
+            return;
+        }
+
+        let step = wait.min(u16::MAX as u32);
+        data.push(VGM_WAIT);
+        data.extend_from_slice(&(step as u16).to_le_bytes());
+        wait -= step;
+    }
+}
+
+/// For every position, the length of the longest common prefix of the
+/// sequence and the sequence starting there.
+fn z_function<T: PartialEq>(sequence: &[T]) -> Vec<usize> {
+    let len = sequence.len();
+    let mut z = vec![0; len];
+    let (mut left, mut right) = (0, 0);
+
+    for i in 1..len {
+        if i < right {
+            z[i] = (right - i).min(z[i - left]);
+        }
+        while i + z[i] < len && sequence[z[i]] == sequence[i + z[i]] {
+            z[i] += 1;
+        }
+        if i + z[i] > right {
+            left = i;
+            right = i + z[i];
+        }
+    }
+
+    z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock cycles of exactly a quarter second, 11025 samples.
+    const QUARTER_SECOND: u32 = SYSCLK_FREQ as u32 / 4;
+    const QUARTER_SECOND_SAMPLES: u32 = VGM_SAMPLE_RATE as u32 / 4;
+
+    /// Logs what `log` writes, returns the finished file.
+    fn vgm(name: &str, loop_detection: bool, log: impl FnOnce(&mut VgmLogger)) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("vgm-test-{}-{}", std::process::id(), name));
+        let mut logger = VgmLogger::new(&path, loop_detection).unwrap();
+        log(&mut logger);
+        logger.finish().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    fn header_value(file: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Writes a few registers, then the same 16 writes three times.
+    fn log_repeating_tail(logger: &mut VgmLogger) {
+        for register in 0..3 {
+            logger.next(QUARTER_SECOND);
+            logger.write(0xFF10 + register, 0x01);
+        }
+        for _ in 0..3 {
+            for value in 0..MIN_LOOP_LEN as u8 {
+                logger.next(QUARTER_SECOND);
+                logger.write(0xFF20, value);
+            }
+        }
+    }
+
+    #[test]
+    fn writes_header_and_commands() {
+        let file = vgm("commands", false, |logger| {
+            logger.write(0xFF26, 0x80);
+            logger.next(SYSCLK_FREQ as u32);
+            logger.write(0xFF25, 0xFF);
+            // A little over one sample
+            logger.next(96);
+        });
+
+        assert_eq!(&file[..4], b"Vgm ");
+        assert_eq!(header_value(&file, VGM_VERSION_OFFSET), VGM_VERSION);
+        assert_eq!(header_value(&file, VGM_TOTAL_SAMPLES_OFFSET), 44101);
+        assert_eq!(header_value(&file, VGM_LOOP_OFFSET), 0);
+        assert_eq!(
+            header_value(&file, VGM_DMG_CLOCK_OFFSET),
+            SYSCLK_FREQ as u32
+        );
+        assert_eq!(
+            header_value(&file, VGM_EOF_OFFSET) as usize,
+            file.len() - VGM_EOF_OFFSET
+        );
+
+        let data_start = VGM_DATA_OFFSET + header_value(&file, VGM_DATA_OFFSET) as usize;
+        assert_eq!(
+            file[data_start..],
+            [
+                VGM_DMG_WRITE,
+                0x16,
+                0x80,
+                VGM_WAIT,
+                0x44,
+                0xAC,
+                VGM_DMG_WRITE,
+                0x15,
+                0xFF,
+                VGM_WAIT_SHORT,
+                VGM_END
+            ]
+        );
+    }
+
+    #[test]
+    fn encodes_waits() {
+        let wait = |samples: u32| {
+            let mut data = Vec::new();
+            push_wait(&mut data, samples);
+            data
+        };
+
+        assert_eq!(wait(0), []);
+        assert_eq!(wait(1), [0x70]);
+        assert_eq!(wait(16), [0x7F]);
+        assert_eq!(wait(17), [VGM_WAIT, 17, 0]);
+        assert_eq!(wait(70000), [VGM_WAIT, 0xFF, 0xFF, VGM_WAIT, 0x71, 0x11]);
+    }
+
+    #[test]
+    fn finds_shortest_repeating_tail() {
+        let z = z_function(&[1, 2, 1, 2, 1, 2]);
+        assert_eq!(z, [0, 0, 4, 0, 2, 0]);
+
+        let file = vgm("loop", true, log_repeating_tail);
+
+        // Intro and a single pass of the loop, 6 bytes per write
+        let writes = 3 + MIN_LOOP_LEN;
+        let data = &file[VGM_HEADER_LEN..];
+        assert_eq!(data.len(), writes * 6 + 3 + 1);
+
+        // The loop starts at the first write after the intro, past its wait
+        assert_eq!(
+            header_value(&file, VGM_LOOP_OFFSET) as usize,
+            VGM_HEADER_LEN + 3 * 6 + 3 - VGM_LOOP_OFFSET
+        );
+        assert_eq!(
+            header_value(&file, VGM_LOOP_SAMPLES_OFFSET),
+            MIN_LOOP_LEN as u32 * QUARTER_SECOND_SAMPLES
+        );
+        assert_eq!(
+            header_value(&file, VGM_TOTAL_SAMPLES_OFFSET),
+            (writes as u32 + 1) * QUARTER_SECOND_SAMPLES
+        );
+    }
+
+    #[test]
+    fn keeps_everything_without_loop_detection() {
+        let file = vgm("no-loop", false, log_repeating_tail);
+
+        let writes = 3 + 3 * MIN_LOOP_LEN;
+        assert_eq!(file.len(), VGM_HEADER_LEN + writes * 6 + 1);
+        assert_eq!(header_value(&file, VGM_LOOP_OFFSET), 0);
+    }
+}
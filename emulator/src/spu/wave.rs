@@ -0,0 +1,182 @@
+use super::length::LengthCounter;
+
+const DAC_ENABLE_BITMASK: u8 = 1 << 7;
+const VOLUME_SHIFT: u8 = 5;
+const VOLUME_BITMASK: u8 = 0b11;
+const LENGTH_ENABLE_BITMASK: u8 = 1 << 6;
+const TRIGGER_BITMASK: u8 = 1 << 7;
+const FREQUENCY_HIGH_BITMASK: u8 = 0b111;
+
+const WAVE_LENGTH: u16 = 256;
+const WAVE_RAM_LEN: usize = 16;
+const WAVE_SAMPLES: u8 = 32;
+
+/// Programmable channel playing back 32 4-bit samples from wave RAM.
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    sample: u8,
+    ram: [u8; WAVE_RAM_LEN],
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(WAVE_LENGTH),
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            ram: [0; WAVE_RAM_LEN],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// Digital output between 0 and 15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        match self.volume_code {
+            0 => 0,
+            code => self.sample >> (code - 1),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    pub fn next(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % WAVE_SAMPLES;
+
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0xF
+            };
+        }
+
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// While the channel plays, the CPU can only reach the byte currently
+    /// being played.
+    pub fn read_ram(&self, offset: u8) -> u8 {
+        if self.enabled {
+            return self.ram[self.position as usize / 2];
+        }
+
+        self.ram[offset as usize]
+    }
+
+    pub fn write_ram(&mut self, offset: u8, value: u8) {
+        let offset = if self.enabled {
+            self.position / 2
+        } else {
+            offset
+        };
+
+        self.ram[offset as usize] = value;
+    }
+
+    /// Reads NR30-NR34, with write-only bits reading as 1.
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0 => {
+                let dac_enabled = if self.dac_enabled {
+                    DAC_ENABLE_BITMASK
+                } else {
+                    0
+                };
+                dac_enabled | !DAC_ENABLE_BITMASK
+            }
+            2 => (self.volume_code << VOLUME_SHIFT) | !(VOLUME_BITMASK << VOLUME_SHIFT),
+            4 => {
+                let length_enabled = if self.length.is_enabled() {
+                    LENGTH_ENABLE_BITMASK
+                } else {
+                    0
+                };
+                length_enabled | !LENGTH_ENABLE_BITMASK
+            }
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes NR30-NR34. `extra_length_clock` tells whether the next frame
+    /// sequencer step skips the length counters.
+    pub fn write(&mut self, register: u8, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = value & DAC_ENABLE_BITMASK != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.volume_code = (value >> VOLUME_SHIFT) & VOLUME_BITMASK,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency =
+                    (self.frequency & 0xFF) | (((value & FREQUENCY_HIGH_BITMASK) as u16) << 8);
+
+                let length_enabled = value & LENGTH_ENABLE_BITMASK != 0;
+                if self.length.set_enabled(length_enabled, extra_length_clock) {
+                    self.enabled = false;
+                }
+
+                if value & TRIGGER_BITMASK != 0 {
+                    self.enabled = self.dac_enabled;
+                    self.length.trigger(extra_length_clock);
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    /// Wave RAM survives powering the APU off.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = std::mem::replace(&mut self.length, LengthCounter::new(WAVE_LENGTH));
+        length.power_off(keep_length);
+        let ram = self.ram;
+
+        *self = WaveChannel::new();
+        self.length = length;
+        self.ram = ram;
+    }
+}
@@ -1,89 +1,105 @@
-use std::io;
-
-use crate::{
-    bus::FetchWrite,
-    constants::{SYSCLK_FREQ, SYSCLK_FREQ_1024, SYSCLK_FREQ_16, SYSCLK_FREQ_256, SYSCLK_FREQ_64},
-    interrupts::Interrupts,
-};
+use crate::{bus::FetchWrite, interrupts::Interrupts};
 
 const DIV_REGISTER_ADDRESS: u16 = 0xFF04;
 const TIMA_REGISTER_ADDRESS: u16 = 0xFF05;
 const TMA_REGISTER_ADDRESS: u16 = 0xFF06;
 const TAC_REGISTER_ADDRESS: u16 = 0xFF07;
 
+const TAC_ENABLE_BITMASK: u8 = 1 << 2;
+const TAC_CLOCK_BITMASK: u8 = 0b11;
+const TAC_UNUSED_BITMASK: u8 = 0xF8;
+
+/// Clock cycles per M-cycle, the timer's step
+const TIMER_STEP: u16 = 4;
+
+/// Counts clock cycles in a 16 bit system counter, DIV being its upper
+/// byte. TIMA increments on falling edges of the counter bit TAC selects,
+/// ANDed with the enable bit, so resetting DIV or changing TAC can
+/// increment it too. An overflow leaves TIMA at 0 for one M-cycle before
+/// TMA is loaded and the interrupt requested.
 pub struct Timer {
-    div: u8,
+    counter: u16,
     tima: u8,
     tma: u8,
     tac: u8,
-    div_increment: f64,
-    tima_increment: f64,
+    /// TIMA overflowed in the last M-cycle, the reload happens in the next
+    overflow_pending: bool,
+    /// TMA was loaded into TIMA in the last M-cycle
+    reloaded: bool,
 }
 
 impl Timer {
     pub fn new() -> Self {
         Timer {
-            div: 0,
+            counter: 0,
             tima: 0,
             tma: 0,
             tac: 0,
-            div_increment: 0.0,
-            tima_increment: 0.0,
+            overflow_pending: false,
+            reloaded: false,
         }
     }
 
-    pub fn next(&mut self, clock_cycles: u8, interrupts: &mut Interrupts) {
-        self.div_increment += clock_cycles as f64 * (SYSCLK_FREQ_256 as f64 / SYSCLK_FREQ as f64);
+    pub fn div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
 
-        if self.div_increment > 1.0 {
-            self.div = self.div.wrapping_add(self.div_increment as u8);
-            self.div_increment -= self.div_increment.floor();
+    pub fn next(&mut self, clock_cycles: u8, interrupts: &mut Interrupts) {
+        for _ in 0..clock_cycles as u16 / TIMER_STEP {
+            self.step(interrupts);
         }
+    }
 
-        let enable_timer = (self.tac & 0b100) != 0;
-        if !enable_timer {
-            return;
+    fn step(&mut self, interrupts: &mut Interrupts) {
+        self.reloaded = false;
+
+        if self.overflow_pending {
+            self.overflow_pending = false;
+            self.tima = self.tma;
+            self.reloaded = true;
+            interrupts.set_timer_request(true);
         }
 
-        let selected_frequency = match self.tac & 0b11 {
-            0b00 => SYSCLK_FREQ_1024,
-            0b01 => SYSCLK_FREQ_16,
-            0b10 => SYSCLK_FREQ_64,
-            0b11 => SYSCLK_FREQ_256,
-            _ => 0,
+        let signal = self.timer_signal();
+        self.counter = self.counter.wrapping_add(TIMER_STEP);
+        self.update_signal(signal);
+    }
+
+    /// Counter bit selected by TAC, ANDed with the enable bit.
+    fn timer_signal(&self) -> bool {
+        let bitmask = match self.tac & TAC_CLOCK_BITMASK {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            _ => 1 << 7,
         };
 
-        self.tima_increment +=
-            clock_cycles as f64 * (selected_frequency as f64 / SYSCLK_FREQ as f64);
-
-        if self.tima_increment > 1.0 {
-            let (value, did_overflow) = self.tima.overflowing_add(self.tima_increment as u8);
-            self.tima_increment -= self.tima_increment.floor();
-            if did_overflow {
-                interrupts.set_timer_request(true);
-                self.tima = self.tma;
-            } else {
-                self.tima = value;
-            }
-        }
+        self.tac & TAC_ENABLE_BITMASK != 0 && self.counter & bitmask != 0
     }
 
-    fn get_address_target(&mut self, address: u16) -> io::Result<&mut dyn FetchWrite> {
-        match address {
-            DIV_REGISTER_ADDRESS => Ok(&mut self.div),
-            TIMA_REGISTER_ADDRESS => Ok(&mut self.tima),
-            TMA_REGISTER_ADDRESS => Ok(&mut self.tma),
-            TAC_REGISTER_ADDRESS => Ok(&mut self.tac),
-            _ => panic!("Accessing unsupported timer address: {:#X}", address),
+    /// Increments TIMA if the signal fell since `old_signal`.
+    fn update_signal(&mut self, old_signal: bool) {
+        if !old_signal || self.timer_signal() {
+            return;
         }
+
+        let (value, did_overflow) = self.tima.overflowing_add(1);
+        self.tima = value;
+        self.overflow_pending = did_overflow;
     }
 }
 
 impl FetchWrite for Timer {
     fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
-        let target = self.get_address_target(address)?;
+        let value = match address {
+            DIV_REGISTER_ADDRESS => self.div(),
+            TIMA_REGISTER_ADDRESS => self.tima,
+            TMA_REGISTER_ADDRESS => self.tma,
+            TAC_REGISTER_ADDRESS => self.tac | TAC_UNUSED_BITMASK,
+            _ => panic!("Accessing unsupported timer address: {:#X}", address),
+        };
 
-        target.fetch8(address)
+        Ok(value)
     }
 
     fn fetch16(&mut self, _: u16) -> Result<u16, std::io::Error> {
@@ -91,23 +107,126 @@ impl FetchWrite for Timer {
     }
 
     fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
-        if address == DIV_REGISTER_ADDRESS {
-            self.div = 0;
-            self.div_increment = 0.0;
-            return Ok(());
-        }
+        let signal = self.timer_signal();
 
-        if address == TAC_REGISTER_ADDRESS {
-            self.div = 0;
-            self.div_increment = 0.0;
+        match address {
+            DIV_REGISTER_ADDRESS => {
+                self.counter = 0;
+                self.update_signal(signal);
+            }
+            TIMA_REGISTER_ADDRESS => {
+                // Writing during the overflow cycle cancels the reload,
+                // writing during the reload cycle is ignored
+                if !self.reloaded {
+                    self.tima = value;
+                    self.overflow_pending = false;
+                }
+            }
+            TMA_REGISTER_ADDRESS => {
+                self.tma = value;
+                if self.reloaded {
+                    self.tima = value;
+                }
+            }
+            TAC_REGISTER_ADDRESS => {
+                self.tac = value & !TAC_UNUSED_BITMASK;
+                self.update_signal(signal);
+            }
+            _ => panic!("Accessing unsupported timer address: {:#X}", address),
         }
 
-        let target = self.get_address_target(address)?;
-
-        target.write8(address, value)
+        Ok(())
     }
 
     fn write16(&mut self, _: u16, _: u16) -> std::io::Result<()> {
         panic!("16 Bit operations unsupported")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enabled, TIMA incrementing every 16 clock cycles on bit 3
+    const TAC_16_CYCLES: u8 = 0b101;
+
+    fn timer() -> (Timer, Interrupts) {
+        let mut timer = Timer::new();
+        timer.write8(TAC_REGISTER_ADDRESS, TAC_16_CYCLES).unwrap();
+
+        (timer, Interrupts::new())
+    }
+
+    #[test]
+    fn div_reset_increments_on_falling_edge() {
+        let (mut timer, mut interrupts) = timer();
+
+        timer.next(8, &mut interrupts);
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 0);
+
+        // Bit 3 of the counter is set, resetting it is a falling edge
+        timer.write8(DIV_REGISTER_ADDRESS, 0).unwrap();
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 1);
+
+        // With the bit clear, a reset changes nothing
+        timer.next(4, &mut interrupts);
+        timer.write8(DIV_REGISTER_ADDRESS, 0).unwrap();
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 1);
+    }
+
+    #[test]
+    fn overflow_reads_zero_for_one_m_cycle() {
+        let (mut timer, mut interrupts) = timer();
+        timer.write8(TMA_REGISTER_ADDRESS, 0xAB).unwrap();
+        timer.write8(TIMA_REGISTER_ADDRESS, 0xFF).unwrap();
+
+        timer.next(16, &mut interrupts);
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 0);
+        assert!(!interrupts.timer_request());
+
+        timer.next(4, &mut interrupts);
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 0xAB);
+        assert!(interrupts.timer_request());
+    }
+
+    #[test]
+    fn tima_write_cancels_pending_reload() {
+        let (mut timer, mut interrupts) = timer();
+        timer.write8(TMA_REGISTER_ADDRESS, 0xAB).unwrap();
+        timer.write8(TIMA_REGISTER_ADDRESS, 0xFF).unwrap();
+
+        timer.next(16, &mut interrupts);
+        timer.write8(TIMA_REGISTER_ADDRESS, 0x12).unwrap();
+        timer.next(4, &mut interrupts);
+
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 0x12);
+        assert!(!interrupts.timer_request());
+    }
+
+    #[test]
+    fn tima_write_ignored_during_reload() {
+        let (mut timer, mut interrupts) = timer();
+        timer.write8(TMA_REGISTER_ADDRESS, 0xAB).unwrap();
+        timer.write8(TIMA_REGISTER_ADDRESS, 0xFF).unwrap();
+
+        timer.next(20, &mut interrupts);
+        timer.write8(TIMA_REGISTER_ADDRESS, 0x12).unwrap();
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 0xAB);
+
+        // TMA written in the reload cycle goes to TIMA as well
+        timer.write8(TMA_REGISTER_ADDRESS, 0xCD).unwrap();
+        assert_eq!(timer.fetch8(TIMA_REGISTER_ADDRESS).unwrap(), 0xCD);
+    }
+
+    #[test]
+    fn tac_reads_unused_bits_set() {
+        let mut timer = Timer::new();
+        assert_eq!(timer.fetch8(TAC_REGISTER_ADDRESS).unwrap(), 0xF8);
+
+        timer.write8(TAC_REGISTER_ADDRESS, 0xFF).unwrap();
+        assert_eq!(timer.fetch8(TAC_REGISTER_ADDRESS).unwrap(), 0xFF);
+
+        timer.write8(TAC_REGISTER_ADDRESS, TAC_16_CYCLES).unwrap();
+        assert_eq!(timer.fetch8(TAC_REGISTER_ADDRESS).unwrap(), 0xFD);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::bus::FetchWrite;
+
+const WRAM_BANK_LEN: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
+
+const BANK_BITMASK: u8 = 0b111;
+
+const SVBK_ADDRESS: u16 = 0xFF70;
+
+/// Work RAM. Bank 0 is fixed at `C000-CFFF`; on CGB, SVBK (`FF70`) selects which of
+/// banks 1-7 is mapped at `D000-DFFF`. The echo area mirrors both.
+pub struct Wram {
+    buffer: Vec<u8>,
+    svbk: u8,
+    cgb: bool,
+}
+
+impl Wram {
+    pub fn new() -> Self {
+        Wram {
+            buffer: vec![0u8; WRAM_BANK_LEN * WRAM_BANKS],
+            svbk: 0,
+            cgb: false,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    fn get_buffer_index(&self, address: u16) -> usize {
+        let offset = (address & 0x1FFF) as usize;
+        if offset < WRAM_BANK_LEN {
+            return offset;
+        }
+
+        // Selecting bank 0 maps bank 1
+        let bank = if self.cgb {
+            ((self.svbk & BANK_BITMASK) as usize).max(1)
+        } else {
+            1
+        };
+
+        bank * WRAM_BANK_LEN + (offset - WRAM_BANK_LEN)
+    }
+}
+
+impl FetchWrite for Wram {
+    fn fetch8(&mut self, address: u16) -> Result<u8, std::io::Error> {
+        if address == SVBK_ADDRESS {
+            return match self.cgb {
+                true => Ok(self.svbk | !BANK_BITMASK),
+                false => Ok(0xFF),
+            };
+        }
+
+        Ok(self.buffer[self.get_buffer_index(address)])
+    }
+
+    fn fetch16(&mut self, address: u16) -> Result<u16, std::io::Error> {
+        let lo = self.fetch8(address)? as u16;
+        let hi = self.fetch8(address.wrapping_add(1))? as u16;
+
+        Ok((hi << 8) | lo)
+    }
+
+    fn write8(&mut self, address: u16, value: u8) -> std::io::Result<()> {
+        if address == SVBK_ADDRESS {
+            if self.cgb {
+                self.svbk = value & BANK_BITMASK;
+            }
+            return Ok(());
+        }
+
+        let index = self.get_buffer_index(address);
+        self.buffer[index] = value;
+
+        Ok(())
+    }
+
+    fn write16(&mut self, address: u16, value: u16) -> std::io::Result<()> {
+        self.write8(address, value as u8)?;
+        self.write8(address.wrapping_add(1), (value >> 8) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wram(cgb: bool) -> Wram {
+        let mut wram = Wram::new();
+        wram.set_cgb_mode(cgb);
+        for bank in 1..WRAM_BANKS as u8 {
+            wram.write8(SVBK_ADDRESS, bank).unwrap();
+            wram.write8(0xD000, bank).unwrap();
+        }
+
+        wram
+    }
+
+    #[test]
+    fn svbk_selects_bank() {
+        let mut wram = wram(true);
+
+        wram.write8(SVBK_ADDRESS, 5).unwrap();
+        assert_eq!(wram.fetch8(0xD000).unwrap(), 5);
+        assert_eq!(wram.fetch8(0xF000).unwrap(), 5);
+        assert_eq!(wram.fetch8(SVBK_ADDRESS).unwrap(), 0xFD);
+    }
+
+    #[test]
+    fn svbk_zero_maps_bank_one() {
+        let mut wram = wram(true);
+
+        wram.write8(SVBK_ADDRESS, 0).unwrap();
+        assert_eq!(wram.fetch8(0xD000).unwrap(), 1);
+        assert_eq!(wram.fetch8(SVBK_ADDRESS).unwrap(), 0xF8);
+    }
+
+    #[test]
+    fn dmg_has_no_banks() {
+        let mut wram = wram(false);
+
+        assert_eq!(wram.fetch8(0xD000).unwrap(), 7);
+        assert_eq!(wram.fetch8(SVBK_ADDRESS).unwrap(), 0xFF);
+    }
+}
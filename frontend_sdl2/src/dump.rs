@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use clap::Args;
+use emulator::gameboy::{GameBoy, GameBoyOptions};
+use emulator::palette::PaletteSet;
+
+#[derive(Args)]
+pub struct DumpArgs {
+    /// Rom file
+    #[clap(value_parser)]
+    file: String,
+
+    /// Path to bootrom file
+    #[clap(short, long, action)]
+    boot_rom: Option<String>,
+
+    /// Number of frames to run before dumping
+    #[clap(short, long, value_parser, default_value_t = 60)]
+    frame: u32,
+
+    /// Directory the PNG files are written to
+    #[clap(short, long, value_parser, default_value = ".")]
+    output: String,
+}
+
+/// Runs the game without a window and writes the screen, tile data, both
+/// tile maps and OAM as PNG files.
+pub fn run(args: DumpArgs) {
+    let options = GameBoyOptions {
+        boot_rom: args.boot_rom,
+        palettes: Some(PaletteSet::default()),
+        ..GameBoyOptions::default()
+    };
+    let mut game_boy = GameBoy::load(&args.file, options).expect("Could not load rom");
+    for _ in 0..args.frame {
+        game_boy.run_frame().unwrap();
+    }
+    let bus = game_boy.bus();
+
+    let output = Path::new(&args.output);
+    let dumps = [
+        ("screen.png", bus.gpu.frame_buffer().clone()),
+        ("tiles.png", bus.gpu.dump_tiles()),
+        ("tilemap_9800.png", bus.gpu.dump_tile_map(false)),
+        ("tilemap_9c00.png", bus.gpu.dump_tile_map(true)),
+        ("oam.png", bus.gpu.dump_oam()),
+    ];
+
+    for (name, frame) in dumps {
+        let path = output.join(name);
+        frame
+            .save_png(&path)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path.display(), e));
+    }
+}
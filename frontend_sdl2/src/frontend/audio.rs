@@ -0,0 +1,99 @@
+use std::{cell::Cell, rc::Rc, thread, time::Duration};
+
+use emulator::spu::AudioSink;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+
+const SAMPLE_RATE: i32 = 48000;
+const AUDIO_CHANNELS: u8 = 2;
+const BYTES_PER_FRAME: u32 = AUDIO_CHANNELS as u32 * 4;
+
+/// Largest deviation from the nominal sample rate dynamic rate control may
+/// use. Small enough that the pitch change can't be heard.
+const MAX_RATE_DELTA: f64 = 0.005;
+/// Weight of the newest measurement in the averaged queue fill
+const FILL_SMOOTHING: f64 = 0.05;
+
+/// Plays the emulator's sound through an SDL2 audio queue. The emulator is
+/// paced by waiting for the queue to drain, and the resampling rate is
+/// nudged up or down to keep the queue at the target latency.
+pub struct Sdl2Audio {
+    queue: Rc<AudioQueue<f32>>,
+    sample_rate: u32,
+    latency_frames: u32,
+    /// Queue fill relative to the target latency before waiting, averaged
+    /// over the last waits
+    fill: Cell<f64>,
+}
+
+impl Sdl2Audio {
+    pub fn new(sdl: &sdl2::Sdl, latency_ms: u32) -> Result<Self, String> {
+        let audio_subsystem = sdl.audio()?;
+
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(AUDIO_CHANNELS),
+            samples: Some(512),
+        };
+        let queue = audio_subsystem.open_queue::<f32, _>(None, &desired)?;
+        queue.resume();
+
+        let sample_rate = queue.spec().freq as u32;
+
+        Ok(Sdl2Audio {
+            queue: Rc::new(queue),
+            sample_rate,
+            latency_frames: sample_rate * latency_ms / 1000,
+            fill: Cell::new(1.0),
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Sink feeding the queue, scaling samples by `volume` between 0 and 1.
+    pub fn sink(&self, volume: f32) -> Sdl2AudioSink {
+        Sdl2AudioSink {
+            queue: self.queue.clone(),
+            volume,
+        }
+    }
+
+    fn queued_frames(&self) -> u32 {
+        self.queue.size() / BYTES_PER_FRAME
+    }
+
+    /// Blocks while more than the target latency is queued. The fill is
+    /// measured first, as the queue is never above the target afterwards.
+    pub fn wait(&self) {
+        let fill = (self.queued_frames() as f64 / self.latency_frames.max(1) as f64).min(2.0);
+        self.fill
+            .set(self.fill.get() + FILL_SMOOTHING * (fill - self.fill.get()));
+
+        while self.queued_frames() > self.latency_frames {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Sample rate to resample to next: slightly higher while the queue is
+    /// below the target latency on average, slightly lower while it is
+    /// above.
+    pub fn adjusted_sample_rate(&self) -> f64 {
+        self.sample_rate as f64 * (1.0 + MAX_RATE_DELTA * (1.0 - self.fill.get()))
+    }
+}
+
+pub struct Sdl2AudioSink {
+    queue: Rc<AudioQueue<f32>>,
+    volume: f32,
+}
+
+impl AudioSink for Sdl2AudioSink {
+    fn push_samples(&mut self, samples: &[f32]) {
+        let samples: Vec<f32> = samples.iter().map(|sample| sample * self.volume).collect();
+
+        if let Err(e) = self.queue.queue_audio(&samples) {
+            eprintln!("Could not queue audio: {}", e);
+        }
+    }
+}
@@ -26,7 +26,6 @@ impl Controller {
                     Keycode::Y => buttons.set_b(true),
                     _ => {}
                 };
-                bus.interrupts.joypad_request();
             }
             Event::KeyUp {
                 keycode: Some(code),
@@ -43,8 +42,6 @@ impl Controller {
                     Keycode::Y => buttons.set_b(false),
                     _ => {}
                 };
-
-                bus.interrupts.joypad_request();
             }
             _ => {}
         }
@@ -1,12 +1,21 @@
+use emulator::constants::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use emulator::gpu::Display;
-use emulator::gpu::DmgColor;
-use sdl2::{pixels::Color, rect::Rect, render::Canvas, video::Window};
+use emulator::gpu::FrameBuffer;
+use sdl2::{
+    pixels::PixelFormatEnum,
+    render::{Canvas, Texture, TextureCreator},
+    video::{Window, WindowContext},
+};
 
-const SCREEN_WIDTH: u8 = 160;
-const SCREEN_HEIGHT: u8 = 144;
+const BYTES_PER_PIXEL: usize = 3;
 
 pub struct Sdl2Display {
     canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+    texture: Texture,
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
 }
 
 impl Sdl2Display {
@@ -21,34 +30,58 @@ impl Sdl2Display {
             .unwrap();
 
         let canvas = window.into_canvas().build().unwrap();
-        Sdl2Display { canvas }
+        let texture_creator = canvas.texture_creator();
+        let texture = create_texture(&texture_creator, SCREEN_WIDTH, SCREEN_HEIGHT);
+
+        Sdl2Display {
+            canvas,
+            texture_creator,
+            texture,
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * BYTES_PER_PIXEL],
+        }
+    }
+
+    /// Recreates the texture when the emulator switches frame sizes, e.g.
+    /// to the SGB border.
+    fn resize(&mut self, width: usize, height: usize) {
+        self.texture = create_texture(&self.texture_creator, width, height);
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height * BYTES_PER_PIXEL];
     }
 }
 
+fn create_texture(
+    texture_creator: &TextureCreator<WindowContext>,
+    width: usize,
+    height: usize,
+) -> Texture {
+    texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .unwrap()
+}
+
 impl Display for Sdl2Display {
-    fn render_pixel(&mut self, x: u8, y: u8, dmg_color: DmgColor) {
-        let (window_width, window_height) = self.canvas.window().size();
-        let viewport_x = ((x as f64 / SCREEN_WIDTH as f64) * window_width as f64).ceil() as i32;
-        let viewport_y = ((y as f64 / SCREEN_HEIGHT as f64) * window_height as f64).ceil() as i32;
-        let viewport_w = ((1.0 / SCREEN_WIDTH as f64) * window_width as f64).ceil() as u32;
-        let viewport_h = ((1.0 / SCREEN_HEIGHT as f64) * window_height as f64).ceil() as u32;
-
-        let color = match dmg_color {
-            DmgColor::Black => Color::RGB(0x00, 0x00, 0x00),
-            DmgColor::DarkGrey => Color::RGB(0x55, 0x55, 0x55),
-            DmgColor::LightGrey => Color::RGB(0xAB, 0xAB, 0xAB),
-            DmgColor::White => Color::RGB(0xFF, 0xFF, 0xFF),
-        };
-
-        let rect = Rect::new(viewport_x, viewport_y, viewport_w, viewport_h);
-
-        self.canvas.set_draw_color(color);
-        self.canvas.fill_rect(rect).unwrap();
-    }
+    fn present(&mut self, frame: &FrameBuffer) {
+        if frame.width() != self.width || frame.height() != self.height {
+            self.resize(frame.width(), frame.height());
+        }
 
-    fn present(&mut self) {
+        for (pixel, dmg_color) in self
+            .pixels
+            .chunks_exact_mut(BYTES_PER_PIXEL)
+            .zip(frame.pixels())
+        {
+            let (r, g, b) = dmg_color.to_rgb();
+            pixel.copy_from_slice(&[r, g, b]);
+        }
+
+        self.texture
+            .update(None, &self.pixels, self.width * BYTES_PER_PIXEL)
+            .unwrap();
+        self.canvas.copy(&self.texture, None, None).unwrap();
         self.canvas.present();
-        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-        self.canvas.clear();
     }
 }
@@ -3,24 +3,33 @@ use std::borrow::Borrow;
 use emulator::bus::Bus;
 use sdl2::{
     event::{Event, WindowEvent},
-    keyboard::Keycode,
+    keyboard::{Keycode, Mod},
     Sdl,
 };
 
-use self::{controller::Controller, display::Sdl2Display};
+use self::{audio::Sdl2Audio, controller::Controller, display::Sdl2Display, scope::ScopeWindow};
+use crate::machine::Machine;
 
+pub mod audio;
 pub mod controller;
 pub mod display;
+pub mod scope;
+
+const DEFAULT_RECORDING_PATH: &str = "recording.wav";
 
 #[allow(dead_code)]
 pub enum FrontendStatus {
     Ok,
     Quit,
+    /// Input should go to the other linked Game Boy
+    SwitchFocus,
     Error,
 }
 pub struct Frontend {
     context: Sdl,
     controller: Controller,
+    recording_path: String,
+    record_stems: bool,
 }
 
 impl Frontend {
@@ -31,10 +40,23 @@ impl Frontend {
         Frontend {
             context: sdl_context,
             controller,
+            recording_path: DEFAULT_RECORDING_PATH.to_string(),
+            record_stems: false,
+        }
+    }
+
+    /// Where the recording hotkey writes the sound output to, keeps the
+    /// default path if none is given.
+    pub fn set_recording(&mut self, path: Option<String>, stems: bool) {
+        if let Some(path) = path {
+            self.recording_path = path;
         }
+        self.record_stems = stems;
     }
 
-    pub fn update(&self, bus: &mut Bus) -> FrontendStatus {
+    /// Input and debug hotkeys act on the focused Game Boy, the sound
+    /// hotkeys on the one that is heard.
+    pub fn update(&self, machine: &mut Machine) -> FrontendStatus {
         let mut event_pump = self.context.event_pump().unwrap();
 
         for event in event_pump.poll_iter() {
@@ -48,18 +70,116 @@ impl Frontend {
                     win_event: WindowEvent::Resized(..),
                     ..
                 } => {}
-                _ => self.controller.update(event, bus),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } if toggle_debug_option(keycode, machine.focused_bus_mut()) => {}
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => return FrontendStatus::SwitchFocus,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => self.toggle_recording(machine.main_bus_mut()),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if toggle_channel(keycode, keymod, machine.main_bus_mut()) => {}
+                _ => self.controller.update(event, machine.focused_bus_mut()),
             }
         }
 
         FrontendStatus::Ok
     }
 
+    /// F6 starts and stops recording the sound output.
+    fn toggle_recording(&self, bus: &mut Bus) {
+        let result = if bus.spu.is_recording() {
+            bus.spu.stop_recording().map(|_| "Recording stopped")
+        } else {
+            bus.spu
+                .start_recording(&self.recording_path, self.record_stems)
+                .map(|_| "Recording started")
+        };
+
+        match result {
+            Ok(message) => println!("{}: {}", message, self.recording_path),
+            Err(e) => eprintln!("Recording failed: {}", e),
+        }
+    }
+
     pub fn new_display(&self, sdl: &sdl2::Sdl) -> Sdl2Display {
         Sdl2Display::new(sdl)
     }
 
+    pub fn new_scope_window(&self, sdl: &sdl2::Sdl) -> ScopeWindow {
+        ScopeWindow::new(sdl)
+    }
+
+    pub fn new_audio(&self, sdl: &sdl2::Sdl, latency_ms: u32) -> Result<Sdl2Audio, String> {
+        Sdl2Audio::new(sdl, latency_ms)
+    }
+
     pub fn get_sdl_context(&self) -> &sdl2::Sdl {
         self.context.borrow()
     }
 }
+
+/// Debug hotkeys: F1 background, F2 window, F3 objects, F4 object limit,
+/// F5 layer tint. Returns whether the key was one of them.
+fn toggle_debug_option(keycode: Keycode, bus: &mut Bus) -> bool {
+    let options = bus.gpu.debug_options_mut();
+    let (name, option) = match keycode {
+        Keycode::F1 => ("Background", &mut options.background),
+        Keycode::F2 => ("Window", &mut options.window),
+        Keycode::F3 => ("Objects", &mut options.objects),
+        Keycode::F4 => ("Object limit", &mut options.object_limit),
+        Keycode::F5 => ("Layer tint", &mut options.layer_tint),
+        _ => return false,
+    };
+
+    *option = !*option;
+    println!("{}: {}", name, if *option { "on" } else { "off" });
+
+    true
+}
+
+/// Sound channel hotkeys: 1-4 mute a channel, Shift+1-4 play it alone.
+/// This also applies to the F6 recording, but not to its stems. Returns
+/// whether the key was one of them.
+fn toggle_channel(keycode: Keycode, keymod: Mod, bus: &mut Bus) -> bool {
+    let channel = match keycode {
+        Keycode::Num1 => 0,
+        Keycode::Num2 => 1,
+        Keycode::Num3 => 2,
+        Keycode::Num4 => 3,
+        _ => return false,
+    };
+
+    let spu = &mut bus.spu;
+    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        let solo = spu.solo() != Some(channel);
+        spu.set_solo(solo.then_some(channel));
+        println!(
+            "Solo channel {}: {}",
+            channel + 1,
+            if solo { "on" } else { "off" }
+        );
+    } else {
+        let muted = !spu.is_channel_muted(channel);
+        spu.set_channel_muted(channel, muted);
+        println!(
+            "Mute channel {}: {}",
+            channel + 1,
+            if muted { "on" } else { "off" }
+        );
+    }
+
+    true
+}
@@ -0,0 +1,68 @@
+use emulator::spu::{Spu, CHANNELS, SCOPE_LEN};
+use sdl2::{pixels::Color, rect::Point, render::Canvas, video::Window};
+
+const SCOPE_WIDTH: u32 = 512;
+const SCOPE_HEIGHT: u32 = 384;
+
+const CHANNEL_COLORS: [Color; CHANNELS] = [
+    Color::RGB(0xFF, 0x60, 0x60),
+    Color::RGB(0xFF, 0xC0, 0x40),
+    Color::RGB(0x60, 0xA0, 0xFF),
+    Color::RGB(0x80, 0xFF, 0x80),
+];
+const MUTED_COLOR: Color = Color::RGB(0x50, 0x50, 0x50);
+
+/// Window showing the recent output of each sound channel as a scrolling
+/// waveform, one above the other.
+pub struct ScopeWindow {
+    canvas: Canvas<Window>,
+    points: Vec<Point>,
+}
+
+impl ScopeWindow {
+    pub fn new(sdl: &sdl2::Sdl) -> Self {
+        let video_subsystem = sdl.video().unwrap();
+
+        let window = video_subsystem
+            .window("sound channels", SCOPE_WIDTH, SCOPE_HEIGHT)
+            .resizable()
+            .build()
+            .unwrap();
+
+        ScopeWindow {
+            canvas: window.into_canvas().build().unwrap(),
+            points: Vec::with_capacity(SCOPE_LEN),
+        }
+    }
+
+    pub fn draw(&mut self, spu: &Spu) {
+        let (width, height) = self.canvas.output_size().unwrap();
+        let row_height = height as f32 / CHANNELS as f32;
+
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+
+        for (channel, color) in CHANNEL_COLORS.into_iter().enumerate() {
+            let center = row_height * (channel as f32 + 0.5);
+
+            self.points.clear();
+            self.points
+                .extend(spu.scope().channel(channel).enumerate().map(|(x, sample)| {
+                    Point::new(
+                        (x as u32 * width / SCOPE_LEN as u32) as i32,
+                        (center - sample * row_height * 0.45) as i32,
+                    )
+                }));
+
+            self.canvas
+                .set_draw_color(if spu.is_channel_audible(channel) {
+                    color
+                } else {
+                    MUTED_COLOR
+                });
+            self.canvas.draw_lines(self.points.as_slice()).unwrap();
+        }
+
+        self.canvas.present();
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::Path;
+
+use clap::Args;
+use emulator::constants::SYSCLK_FREQ;
+use emulator::gbs::GbsPlayer;
+
+#[derive(Args)]
+pub struct GbsArgs {
+    /// GBS file
+    #[clap(value_parser)]
+    file: String,
+
+    /// Print the header and the tracks instead of playing
+    #[clap(short, long, action)]
+    list: bool,
+
+    /// Track to play, counting from 1. Can be repeated, plays all tracks
+    /// from the first song by default
+    #[clap(short, long, value_parser)]
+    track: Vec<u8>,
+
+    /// Seconds to play each track for
+    #[clap(short, long, value_parser, default_value_t = 120)]
+    seconds: u32,
+
+    /// Directory the WAV files are written to
+    #[clap(short, long, value_parser, default_value = ".")]
+    output: String,
+
+    /// Also record each sound channel to a WAV file of its own
+    #[clap(long, action)]
+    stems: bool,
+}
+
+/// Plays tracks of a GBS file without a window and writes each one to a
+/// WAV file.
+pub fn run(args: GbsArgs) {
+    let mut player = GbsPlayer::load(&args.file)
+        .unwrap_or_else(|e| panic!("Could not load {}: {}", args.file, e));
+    let header = player.header().clone();
+
+    if args.list {
+        println!("Title:     {}", header.title);
+        println!("Author:    {}", header.author);
+        println!("Copyright: {}", header.copyright);
+        println!(
+            "Tracks:    {} (first: {})",
+            header.song_count, header.first_song
+        );
+        println!(
+            "Load {:#06X}, init {:#06X}, play {:#06X}, stack {:#06X}, TMA {:#04X}, TAC {:#04X}",
+            header.load_address,
+            header.init_address,
+            header.play_address,
+            header.stack_pointer,
+            header.timer_modulo,
+            header.timer_control
+        );
+        return;
+    }
+
+    let tracks = if args.track.is_empty() {
+        (header.first_song.max(1)..=header.song_count).collect()
+    } else {
+        args.track
+    };
+
+    for track in tracks {
+        if track == 0 || track > header.song_count {
+            eprintln!("No track {}, skipping", track);
+            continue;
+        }
+
+        let path = Path::new(&args.output).join(format!("track_{:02}.wav", track));
+        println!("Track {}: {}", track, path.display());
+
+        player
+            .spu_mut()
+            .start_recording(&path, args.stems)
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path.display(), e));
+        player.start_song(track - 1).unwrap();
+        player.run(args.seconds as i64 * SYSCLK_FREQ).unwrap();
+        player
+            .spu_mut()
+            .stop_recording()
+            .unwrap_or_else(|e| panic!("Could not write {}: {}", path.display(), e));
+    }
+}
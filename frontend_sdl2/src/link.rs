@@ -0,0 +1,216 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use emulator::serial::SerialLink;
+
+/// Clock cycles both emulators run between two synchronizations. Events
+/// from the peer take effect one period after they happened, so what one
+/// side sees of the other, its SB and whether it waits for the clock, is
+/// up to 4096 cycles (about 1 ms) old. Games give the other side far more
+/// time than that to get ready, and the fixed delay keeps runs repeatable.
+const SYNC_CYCLES: u64 = 4096;
+
+const EVENT_PULSE: u8 = 0;
+const EVENT_READY: u8 = 1;
+const EVENT_NOT_READY: u8 = 2;
+
+#[derive(Clone, Copy)]
+enum LinkEvent {
+    /// A clock pulse with the bit shifted out
+    Pulse(bool),
+    /// Waiting for the peer's clock with this SB
+    Ready(u8),
+    NotReady,
+}
+
+impl LinkEvent {
+    fn encode(&self) -> [u8; 2] {
+        match *self {
+            LinkEvent::Pulse(bit) => [EVENT_PULSE, bit as u8],
+            LinkEvent::Ready(sb) => [EVENT_READY, sb],
+            LinkEvent::NotReady => [EVENT_NOT_READY, 0],
+        }
+    }
+
+    fn decode(bytes: [u8; 2]) -> io::Result<Self> {
+        match bytes[0] {
+            EVENT_PULSE => Ok(LinkEvent::Pulse(bytes[1] != 0)),
+            EVENT_READY => Ok(LinkEvent::Ready(bytes[1])),
+            EVENT_NOT_READY => Ok(LinkEvent::NotReady),
+            kind => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown link event: {}", kind),
+            )),
+        }
+    }
+}
+
+/// Link cable to another emulator over TCP. Both sides run in lockstep:
+/// every `SYNC_CYCLES` they exchange the events of the last period with
+/// their cycle timestamps and wait for each other, so transfers play out
+/// the same way on every run.
+///
+/// The side waiting for the clock announces its SB, which lets the
+/// clocking side answer each bit without a round trip.
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+    cycles: u64,
+    next_sync: u64,
+    outgoing: Vec<(u64, LinkEvent)>,
+    incoming: VecDeque<(u64, LinkEvent)>,
+    waiting: bool,
+    /// Pulses from the peer that are due
+    pulses: VecDeque<bool>,
+    /// SB of the peer while it waits for our clock, and the bits it
+    /// shifted out since
+    peer_sb: Option<u8>,
+    peer_shifted: u8,
+}
+
+impl TcpLink {
+    /// Waits for the other emulator to connect on `port` of the local
+    /// address `bind_address`.
+    pub fn listen(bind_address: &str, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((bind_address, port))?;
+        println!(
+            "Waiting for link cable connection on {}",
+            listener.local_addr()?
+        );
+        let (stream, address) = listener.accept()?;
+        println!("Link cable connected to {}", address);
+
+        TcpLink::new(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        TcpLink::new(TcpStream::connect(address)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        Ok(TcpLink {
+            stream: Some(stream),
+            cycles: 0,
+            next_sync: SYNC_CYCLES,
+            outgoing: Vec::new(),
+            incoming: VecDeque::new(),
+            waiting: false,
+            pulses: VecDeque::new(),
+            peer_sb: None,
+            peer_shifted: 0,
+        })
+    }
+
+    fn send(&mut self, event: LinkEvent) {
+        self.outgoing.push((self.cycles, event));
+    }
+
+    /// Sends our events since the last synchronization and receives the
+    /// peer's for the same period.
+    fn sync(&mut self) -> io::Result<()> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+
+        let mut message = Vec::with_capacity(4 + self.outgoing.len() * 10);
+        message.extend_from_slice(&(self.outgoing.len() as u32).to_le_bytes());
+        for (timestamp, event) in self.outgoing.drain(..) {
+            message.extend_from_slice(&timestamp.to_le_bytes());
+            message.extend_from_slice(&event.encode());
+        }
+        stream.write_all(&message)?;
+
+        let mut count = [0; 4];
+        stream.read_exact(&mut count)?;
+        for _ in 0..u32::from_le_bytes(count) {
+            let mut timestamp = [0; 8];
+            let mut event = [0; 2];
+            stream.read_exact(&mut timestamp)?;
+            stream.read_exact(&mut event)?;
+
+            let due = u64::from_le_bytes(timestamp) + SYNC_CYCLES;
+            self.incoming.push_back((due, LinkEvent::decode(event)?));
+        }
+
+        Ok(())
+    }
+
+    fn apply_due_events(&mut self) {
+        while let Some(&(due, event)) = self.incoming.front() {
+            if due > self.cycles {
+                break;
+            }
+            self.incoming.pop_front();
+
+            match event {
+                LinkEvent::Pulse(bit) => self.pulses.push_back(bit),
+                LinkEvent::Ready(sb) => {
+                    self.peer_sb = Some(sb);
+                    self.peer_shifted = 0;
+                }
+                LinkEvent::NotReady => self.peer_sb = None,
+            }
+        }
+    }
+
+    fn disconnect(&mut self, error: io::Error) {
+        eprintln!("Link cable disconnected: {}", error);
+        self.stream = None;
+        self.incoming.clear();
+        self.pulses.clear();
+        self.peer_sb = None;
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn exchange(&mut self, bit: bool) -> bool {
+        self.send(LinkEvent::Pulse(bit));
+
+        match self.peer_sb {
+            Some(sb) => {
+                let peer_bit = sb & (0x80 >> self.peer_shifted) != 0;
+                self.peer_shifted += 1;
+                if self.peer_shifted == 8 {
+                    self.peer_sb = None;
+                }
+                peer_bit
+            }
+            None => true,
+        }
+    }
+
+    fn poll(&mut self, sb: Option<u8>) -> Option<bool> {
+        match (self.waiting, sb) {
+            (false, Some(sb)) => self.send(LinkEvent::Ready(sb)),
+            (true, None) => self.send(LinkEvent::NotReady),
+            _ => {}
+        }
+        self.waiting = sb.is_some();
+
+        if !self.waiting {
+            // Pulses nobody waits for are lost
+            self.pulses.clear();
+            return None;
+        }
+
+        self.pulses.pop_front()
+    }
+
+    fn next(&mut self, clock_cycles: u8) {
+        self.cycles += clock_cycles as u64;
+
+        if self.cycles >= self.next_sync {
+            self.next_sync += SYNC_CYCLES;
+            if let Err(e) = self.sync() {
+                self.disconnect(e);
+            }
+        }
+
+        self.apply_due_events();
+    }
+}
@@ -0,0 +1,89 @@
+use emulator::bus::Bus;
+use emulator::gameboy::GameBoy;
+use emulator::gpu::FrameBuffer;
+use emulator::link::LinkedGameBoys;
+
+/// What the window shows: a single Game Boy, or two linked ones side by
+/// side. Input goes to the focused one, sound comes from the first.
+pub enum Machine {
+    Single(Box<GameBoy>),
+    Linked {
+        game_boys: Box<LinkedGameBoys>,
+        focus: usize,
+        frame: FrameBuffer,
+    },
+}
+
+impl Machine {
+    pub fn linked(first: GameBoy, second: GameBoy) -> Self {
+        Machine::Linked {
+            game_boys: Box::new(LinkedGameBoys::new(first.into_parts(), second.into_parts())),
+            focus: 0,
+            frame: FrameBuffer::new(0, 0),
+        }
+    }
+
+    /// Runs the next instruction, returns the clock cycles that passed.
+    pub fn step(&mut self) -> i64 {
+        match self {
+            Machine::Single(game_boy) => game_boy.step().unwrap() as i64,
+            Machine::Linked { game_boys, .. } => game_boys.step().unwrap(),
+        }
+    }
+
+    pub fn take_frame(&mut self) -> Option<&FrameBuffer> {
+        match self {
+            Machine::Single(game_boy) => game_boy.take_frame(),
+            Machine::Linked {
+                game_boys, frame, ..
+            } => {
+                game_boys.bus_mut(0).gpu.take_frame()?;
+                game_boys.bus_mut(1).gpu.take_frame();
+
+                compose_side_by_side(game_boys.frame_buffer(0), game_boys.frame_buffer(1), frame);
+                Some(frame)
+            }
+        }
+    }
+
+    /// The Game Boy that plays sound.
+    pub fn main_bus_mut(&mut self) -> &mut Bus {
+        match self {
+            Machine::Single(game_boy) => game_boy.bus_mut(),
+            Machine::Linked { game_boys, .. } => game_boys.bus_mut(0),
+        }
+    }
+
+    /// The Game Boy that receives input.
+    pub fn focused_bus_mut(&mut self) -> &mut Bus {
+        match self {
+            Machine::Single(game_boy) => game_boy.bus_mut(),
+            Machine::Linked {
+                game_boys, focus, ..
+            } => game_boys.bus_mut(*focus),
+        }
+    }
+
+    pub fn switch_focus(&mut self) {
+        if let Machine::Linked { focus, .. } = self {
+            *focus = 1 - *focus;
+            println!("Input goes to Game Boy {}", *focus + 1);
+        }
+    }
+}
+
+fn compose_side_by_side(left: &FrameBuffer, right: &FrameBuffer, output: &mut FrameBuffer) {
+    let width = left.width() + right.width();
+    let height = left.height().max(right.height());
+    if output.width() != width || output.height() != height {
+        *output = FrameBuffer::new(width, height);
+    }
+
+    for (offset, frame) in [(0, left), (left.width(), right)] {
+        for y in 0..frame.height() {
+            for x in 0..frame.width() {
+                output.set_pixel(offset + x, y, frame.get_pixel(x, y));
+            }
+        }
+    }
+}
@@ -1,20 +1,32 @@
-use clap::Parser;
-use emulator::bus::Bus;
-use emulator::cartridge::Cartridge;
+use clap::{Parser, Subcommand};
+use dump::DumpArgs;
 use emulator::constants::{BATCH_DURATION_MS, GRANULARITY};
-use emulator::cpu::Cpu;
-use emulator::gpu::Gpu;
+use emulator::gameboy::{GameBoy, GameBoyOptions};
+use emulator::gpu::{Display, FrameBlend};
+use emulator::palette::{ColorCorrection, PaletteSet};
+use emulator::printer::Printer;
 use frontend::{Frontend, FrontendStatus};
+use gbs::GbsArgs;
+use link::TcpLink;
+use machine::Machine;
 use std::{sync::mpsc::channel, time::Duration};
 
+mod dump;
 mod frontend;
+mod gbs;
+mod link;
+mod machine;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Rom file
-    #[clap(value_parser)]
-    file: String,
+    #[clap(value_parser, required = true)]
+    file: Option<String>,
 
     /// Path to bootrom file
     #[clap(short, long, action)]
@@ -23,50 +35,257 @@ struct Cli {
     /// Print disassembly
     #[clap(short, long, action)]
     disassemble: bool,
+
+    /// Run as Super Game Boy if the game supports it
+    #[clap(long, action)]
+    sgb: bool,
+
+    /// DMG palette: a preset (grey, dmg, pocket, light, up, up-a, ...,
+    /// right-b), `auto` for the CGB boot ROM palette of the game, or a
+    /// palette file
+    #[clap(short, long, value_parser, default_value = "grey")]
+    palette: String,
+
+    /// CGB color correction: none, curves or desaturate
+    #[clap(long, value_parser, default_value = "none")]
+    color_correction: ColorCorrection,
+
+    /// Blend frames to simulate LCD ghosting: none, mix[:WEIGHT] or
+    /// lcd[:RESPONSE], values between 0 and 1
+    #[clap(long, value_parser, default_value = "none")]
+    frame_blend: FrameBlend,
+
+    /// Audio volume in percent
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=100), default_value = "100")]
+    volume: u8,
+
+    /// Play no sound
+    #[clap(long, action)]
+    mute: bool,
+
+    /// Audio buffer latency in milliseconds
+    #[clap(long, value_parser, default_value = "64")]
+    audio_latency: u32,
+
+    /// Record the sound output to a WAV file from the start. F6 starts and
+    /// stops recording to this file, `recording.wav` by default. Channels
+    /// muted with 1-4 or Shift+1-4 are left out of the recording too
+    #[clap(long, value_parser)]
+    record: Option<String>,
+
+    /// Also record each sound channel to a WAV file of its own
+    #[clap(long, action)]
+    record_stems: bool,
+
+    /// Log sound register writes to a VGM file
+    #[clap(long, value_parser)]
+    vgm: Option<String>,
+
+    /// Cut the VGM log after its repeating tail and loop it
+    #[clap(long, action)]
+    vgm_loop: bool,
+
+    /// Show the waveforms of the sound channels in a second window
+    #[clap(long, action)]
+    scope: bool,
+
+    /// Wait for another emulator to connect a link cable on this port
+    #[clap(long, value_parser, conflicts_with = "link-connect")]
+    link_listen: Option<u16>,
+
+    /// Local address to wait for the link cable connection on. Use 0.0.0.0
+    /// to accept connections from other machines
+    #[clap(long, value_parser, default_value = "127.0.0.1")]
+    link_bind: String,
+
+    /// Connect a link cable to another emulator at HOST:PORT
+    #[clap(long, value_parser)]
+    link_connect: Option<String>,
+
+    /// Run a second Game Boy with this rom next to the first, connected by
+    /// a link cable. Tab switches which one gets the input
+    #[clap(long, value_parser, conflicts_with_all = &["link-listen", "link-connect"])]
+    link_local: Option<String>,
+
+    /// Connect a Game Boy Printer and write its prints as PNG files to
+    /// this directory
+    #[clap(long, value_parser, conflicts_with_all = &["link-listen", "link-connect", "link-local"])]
+    printer: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write PNG dumps of the screen, VRAM tiles, tile maps and OAM
+    Dump(DumpArgs),
+    /// Play tracks of a GBS music file into WAV files
+    Gbs(GbsArgs),
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let frontend = Frontend::new();
+    match cli.command {
+        Some(Command::Dump(args)) => return dump::run(args),
+        Some(Command::Gbs(args)) => return gbs::run(args),
+        None => {}
+    }
+
+    let mut frontend = Frontend::new();
+    frontend.set_recording(cli.record.clone(), cli.record_stems);
     let sdl_context = frontend.get_sdl_context();
     let mut display = frontend.new_display(sdl_context);
+    let mut scope_window = cli.scope.then(|| frontend.new_scope_window(sdl_context));
 
-    let skip_boot = cli.boot_rom.is_none();
-    let mut cpu = Cpu::new(skip_boot, cli.disassemble);
-    let cartridge = Cartridge::new(cli.file.as_str());
-    let gpu = Gpu::new(&mut display);
-    let mut bus = Bus::new(cartridge, gpu, cli.boot_rom);
+    let mut game_boy = new_game_boy(cli.file.as_ref().unwrap(), &cli);
 
-    let (tick_tx, tick_rx) = channel();
+    // Pace the emulation on the audio queue, or on a timer if there is no
+    // audio device
+    let audio = match frontend.new_audio(sdl_context, cli.audio_latency) {
+        Ok(audio) => {
+            let volume = if cli.mute {
+                0.0
+            } else {
+                cli.volume as f32 / 100.0
+            };
+            game_boy
+                .spu_mut()
+                .set_sink(Box::new(audio.sink(volume)), audio.sample_rate());
+            Some(audio)
+        }
+        Err(e) => {
+            eprintln!("Could not open audio device: {}", e);
+            None
+        }
+    };
 
-    ::std::thread::spawn(move || {
-        loop {
-            std::thread::sleep(Duration::from_millis(BATCH_DURATION_MS));
-            if let Err(_) = tick_tx.send(()) {
-                // End thread
-                return;
-            }
+    if let Some(path) = &cli.record {
+        if let Err(e) = game_boy.spu_mut().start_recording(path, cli.record_stems) {
+            eprintln!("Recording failed: {}", e);
         }
+    }
+
+    let link = match (cli.link_listen, &cli.link_connect) {
+        (Some(port), _) => Some(TcpLink::listen(&cli.link_bind, port)),
+        (None, Some(address)) => Some(TcpLink::connect(address.as_str())),
+        (None, None) => None,
+    };
+    match link {
+        Some(Ok(link)) => game_boy.bus_mut().set_serial_link(Some(Box::new(link))),
+        Some(Err(e)) => eprintln!("Could not connect link cable: {}", e),
+        None => {}
+    }
+
+    let print_results = cli.printer.as_ref().map(|directory| {
+        let printer = Printer::new(directory);
+        let results = printer.results();
+        game_boy.bus_mut().set_serial_link(Some(Box::new(printer)));
+        results
     });
 
+    if let Some(path) = &cli.vgm {
+        if let Err(e) = game_boy.spu_mut().start_vgm_log(path, cli.vgm_loop) {
+            eprintln!("VGM logging failed: {}", e);
+        }
+    }
+
+    let (tick_tx, tick_rx) = channel();
+
+    if audio.is_none() {
+        ::std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(Duration::from_millis(BATCH_DURATION_MS));
+                if tick_tx.send(()).is_err() {
+                    // End thread
+                    return;
+                }
+            }
+        });
+    }
+
+    let mut machine = match &cli.link_local {
+        Some(path) => Machine::linked(game_boy, new_game_boy(path, &cli)),
+        None => Machine::Single(Box::new(game_boy)),
+    };
+
     let mut cycles = 0;
 
     'running: loop {
         while cycles < GRANULARITY {
-            let cpu_cycles = cpu.next(&mut bus).unwrap() as i64;
-            cycles += cpu_cycles;
+            cycles += machine.step();
+
+            if let Some(frame) = machine.take_frame() {
+                display.present(frame);
+
+                if let Some(scope_window) = scope_window.as_mut() {
+                    scope_window.draw(&machine.main_bus_mut().spu);
+                }
+            }
         }
 
         cycles -= GRANULARITY;
 
-        if let Err(e) = tick_rx.recv() {
-            panic!("Timer died: {:?}", e);
+        let bus = machine.main_bus_mut();
+        match &audio {
+            Some(audio) => {
+                bus.spu.flush_samples();
+                audio.wait();
+                bus.spu.set_sample_rate(audio.adjusted_sample_rate());
+            }
+            None => {
+                if let Err(e) = tick_rx.recv() {
+                    panic!("Timer died: {:?}", e);
+                }
+            }
         }
 
-        match frontend.update(&mut bus) {
+        if let Some(results) = &print_results {
+            for result in results.borrow_mut().drain(..) {
+                match result {
+                    Ok(path) => println!("Printed {}", path.display()),
+                    Err(e) => eprintln!("Printing failed: {}", e),
+                }
+            }
+        }
+
+        match frontend.update(&mut machine) {
             FrontendStatus::Quit => break 'running,
+            FrontendStatus::SwitchFocus => machine.switch_focus(),
             _ => {}
         }
     }
+
+    let bus = machine.main_bus_mut();
+    if let Err(e) = bus.spu.stop_recording() {
+        eprintln!("Recording failed: {}", e);
+    }
+    if let Err(e) = bus.spu.stop_vgm_log() {
+        eprintln!("VGM logging failed: {}", e);
+    }
+}
+
+/// Sets up a Game Boy for the rom at `path` with the options of the command
+/// line.
+fn new_game_boy(path: &str, cli: &Cli) -> GameBoy {
+    let palettes = match cli.palette.as_str() {
+        "auto" => None,
+        name => Some(
+            PaletteSet::preset(name)
+                .unwrap_or_else(|| PaletteSet::load(name).expect("Could not load palette")),
+        ),
+    };
+    let options = GameBoyOptions {
+        boot_rom: cli.boot_rom.clone(),
+        disassemble: cli.disassemble,
+        sgb: cli.sgb,
+        palettes,
+        color_correction: cli.color_correction,
+        frame_blend: cli.frame_blend,
+    };
+
+    let game_boy = GameBoy::load(path, options).expect("Could not load rom");
+    if cli.sgb && !game_boy.bus().cartridge().supports_sgb() {
+        eprintln!("Game does not support SGB functions");
+    }
+
+    game_boy
 }